@@ -1,37 +1,44 @@
 use std::{
     cell::{Cell, RefCell},
-    fs::{self, metadata, ReadDir},
-    rc::Rc, path::Path, env::consts::OS, sync::{Arc},
-    time::SystemTime
+    collections::HashSet,
+    fs::{self, metadata, Metadata}, io,
+    sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex},
+    thread,
+    time::Duration,
+    path::{Path, PathBuf}, env::consts::OS,
 };
 
-use chrono::{Local, DateTime};
-
-use colored::*;
-
-use dialoguer::{theme::ColorfulTheme, Select};
-
-use egui::mutex::RwLock;
+use chrono::Local;
 use glob::Pattern;
-// use sysinfo::{ System };
-use sysinfo::{DiskExt, System, SystemExt};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    errors::{BrokenSymlink, ScanError, ScanOp, SymlinkIssue},
+    filter::{Filter, FilterError},
+    junk::JunkKind,
+    progress::{expected_bytes, ProgressEmitter, ProgressEvent},
+    scan::Scan,
     stats::AnalyzerStats,
-    utils::{bytes_to_human, is_hidden}, app::Scan
+    utils::{device_id, file_id, is_hidden},
 };
 
-#[derive(Clone)]
+/// A scanned directory. The tree only holds directories; files are summarized in
+/// their parent's `len`, which is in units of the scan's `UsageMode`.
+#[derive(Clone, Debug)]
 pub struct FileTreeNode {
-    path: String,
+    pub path: PathBuf,
     pub is_file: bool,
     // mime_type: String,
     pub len: u64,
-    // children: RefCell<Vec<FileTreeNode>>,
+    /// Files under it, at any depth
+    pub files: u64,
+    pub children: Vec<FileTreeNode>,
+    /// The directory holds nothing but empty directories
+    pub empty: bool,
 }
 
 impl FileTreeNode {
-    fn new(path: String, is_file: bool, len: u64) -> FileTreeNode {
+    fn new(path: PathBuf, is_file: bool, len: u64) -> FileTreeNode {
         /*
         let mut mime_str = String::from("");
         if let Some(mime) = mime_guess::from_path(path.clone()).first() {
@@ -40,144 +47,588 @@ impl FileTreeNode {
         */
 
         FileTreeNode {
-            path: path,
+            path,
             // mime_type: mime_str,
             is_file,
             len,
-            // children: RefCell::new(Vec::new()),
+            files: 0,
+            children: vec![],
+            empty: !is_file,
         }
     }
+}
 
-    /*
-    fn push_child(&self, path: &str, is_file: bool, len: u64) -> Box<FileTreeNode> {
-        let child = Box::new(FileTreeNode::new(String::from(path), is_file, len));
+/// What the analyzer measures when ranking files and directories
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageMode {
+    /// Apparent size in bytes
+    Bytes,
+    /// Number of inodes (files and directories)
+    Files,
+}
 
-        self.children.borrow_mut().push((*child.as_ref()).clone());
+impl UsageMode {
+    /// How much a single entry of `len` bytes contributes to usage in this mode
+    pub fn weight(&self, len: u64) -> u64 {
+        match self {
+            UsageMode::Bytes => len,
+            UsageMode::Files => 1,
+        }
+    }
 
-        child
+    /// The bytes a usage value in this mode stands for, 0 when it's a file count
+    pub fn bytes(&self, usage: u64) -> u64 {
+        match self {
+            UsageMode::Bytes => usage,
+            UsageMode::Files => 0,
+        }
     }
-    */
 }
 
+#[derive(Clone, Debug)]
 pub struct ScanSettings {
-    pub dir: String,
-    pub ignore: String,
+    pub dirs: Vec<PathBuf>,
+    /// Exclude patterns in gitignore syntax, `!pattern` re-includes
+    pub ignore: Vec<String>,
+    /// Files of further patterns in gitignore syntax
+    pub exclude_files: Vec<PathBuf>,
+    /// Honor `.gitignore` files found during the walk
+    pub use_gitignore: bool,
+    /// Globs matching directories of re-creatable developer files, like `**/node_modules`
+    pub developer_patterns: Vec<String>,
     pub nlargest: u64,
     pub largebytes: u64,
     pub hidden: bool,
+    /// Walk into symlinked directories instead of skipping them
+    pub follow_symlinks: bool,
+    /// Don't cross into other filesystems mounted below a root
+    pub one_filesystem: bool,
+    /// Number of threads walking each root
+    pub threads: usize,
+    pub mode: UsageMode,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        ScanSettings {
+            dirs: vec![],
+            ignore: vec![],
+            exclude_files: vec![],
+            use_gitignore: false,
+            developer_patterns: vec![String::from("**/node_modules")],
+            nlargest: 100,
+            largebytes: 1024 * 1024 * 50,
+            hidden: true,
+            follow_symlinks: false,
+            one_filesystem: false,
+            threads: 1,
+            mode: UsageMode::Bytes,
+        }
+    }
+}
+
+impl ScanSettings {
+    pub fn builder() -> ScanSettingsBuilder {
+        ScanSettingsBuilder { settings: ScanSettings::default() }
+    }
+}
+
+/// Builds `ScanSettings`, starting from the defaults
+pub struct ScanSettingsBuilder {
+    settings: ScanSettings,
+}
+
+impl ScanSettingsBuilder {
+    /// Adds a directory to scan
+    pub fn dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.settings.dirs.push(dir.into());
+        self
+    }
+
+    /// Adds an exclude pattern in gitignore syntax
+    pub fn ignore<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.settings.ignore.push(pattern.into());
+        self
+    }
+
+    /// Adds a file of exclude patterns in gitignore syntax
+    pub fn exclude_file<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.settings.exclude_files.push(file.into());
+        self
+    }
+
+    pub fn use_gitignore(mut self, use_gitignore: bool) -> Self {
+        self.settings.use_gitignore = use_gitignore;
+        self
+    }
+
+    /// Replaces the developer directory globs
+    pub fn developer_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.settings.developer_patterns = patterns;
+        self
+    }
+
+    /// How many of the largest files to keep
+    pub fn nlargest(mut self, nlargest: u64) -> Self {
+        self.settings.nlargest = nlargest;
+        self
+    }
+
+    /// Files smaller than this are never listed as large
+    pub fn largebytes(mut self, largebytes: u64) -> Self {
+        self.settings.largebytes = largebytes;
+        self
+    }
+
+    /// Whether to include hidden files and directories
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.settings.hidden = hidden;
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.settings.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn one_filesystem(mut self, one_filesystem: bool) -> Self {
+        self.settings.one_filesystem = one_filesystem;
+        self
+    }
+
+    /// How many threads walk each root. Values below 1 are treated as 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.settings.threads = threads;
+        self
+    }
+
+    pub fn mode(mut self, mode: UsageMode) -> Self {
+        self.settings.mode = mode;
+        self
+    }
+
+    pub fn build(self) -> ScanSettings {
+        self.settings
+    }
 }
 
+/// Scans the configured directories, calling `on_progress` as it goes
+pub fn scan<F>(settings: ScanSettings, on_progress: F) -> Result<Scan, FilterError>
+where
+    F: FnMut(&ProgressEvent) + 'static,
+{
+    let analyzer = Analyzer::new(settings)?;
+    analyzer.on_progress(on_progress);
 
-pub struct Analyzer<'a> {
+    Ok(analyzer.analyze())
+}
+
+pub struct Analyzer {
     total_bytes: Cell<u64>,
-    tree: Rc<FileTreeNode>,
+    roots: Vec<PathBuf>,
     pub stats: RefCell<AnalyzerStats>,
     // files: RefCell<Vec<Box<FileTreeNode>>>,
-    ignore_pattern: Pattern,
-    settings: &'a ScanSettings,
-    scan_results: Arc<RwLock<Scan>>
+    filter: Filter,
+    settings: ScanSettings,
+    /// Device of the root being walked, set when staying on one filesystem
+    root_dev: Cell<Option<u64>>,
+    /// (device, inode) of everything counted, when following symlinks
+    seen: Arc<Mutex<HashSet<(u64, u64)>>>,
+    progress: ProgressEmitter,
 }
 
-impl<'a> Analyzer<'a> {
-    pub fn new(settings: &'a ScanSettings, scan_results: Arc<RwLock<Scan>>) -> Analyzer<'a> {
-        let stats = RefCell::new(AnalyzerStats::new());
+impl Analyzer {
+    pub fn new(settings: ScanSettings) -> Result<Analyzer, FilterError> {
+        let mut developer_patterns = vec![];
+        for pattern in settings.developer_patterns.iter() {
+            developer_patterns.push(Pattern::new(pattern).map_err(|e| FilterError {
+                source: String::from("developer patterns"),
+                pattern: pattern.clone(),
+                message: String::from(e.msg),
+            })?);
+        }
+
+        let stats = RefCell::new(AnalyzerStats::new(
+            settings.mode,
+            developer_patterns,
+            settings.nlargest,
+            settings.largebytes,
+        ));
+
+        let filter = Filter::new(&settings.ignore, &settings.exclude_files, settings.use_gitignore)?;
 
-        Analyzer {
-            tree: Rc::new(FileTreeNode::new(settings.dir.clone(), false, 0)),
+        Ok(Analyzer {
+            roots: dedupe_roots(&settings.dirs),
             stats,
             total_bytes: Cell::new(0),
             // files: RefCell::new(Vec::new()),
-            ignore_pattern: Pattern::new(settings.ignore.as_str()).expect("Unable to parse ignore glob pattern"),
+            filter,
             settings,
-            scan_results
-        }
+            root_dev: Cell::new(None),
+            seen: Arc::new(Mutex::new(HashSet::new())),
+            progress: ProgressEmitter::new(),
+        })
     }
 
-    pub fn analyze(&self) -> std::io::Result<()> {
-        self.read_dir(self.tree.path.as_str());
-
-        let mut w = self.scan_results.write();
+    /// Receive progress events for the scan. Updates are throttled, so
+    /// subscribers never slow down the walk.
+    pub fn subscribe(&self) -> Receiver<ProgressEvent> {
+        self.progress.subscribe()
+    }
 
-        let stats = self.stats.borrow();
+    pub fn add_progress_listener(&self, tx: Sender<ProgressEvent>) {
+        self.progress.add_listener(tx);
+    }
 
-        (*w).current_file = None;
-        (*w).largest_files = stats.largest_files.clone();
-        (*w).completed_at = Some(Local::now());
-        (*w).num_files = stats.num_files;
-        (*w).dev_total_usage = stats.dev_total_usage;
-        (*w).total_archives = stats.total_archives;
-        (*w).total_binaries = stats.total_binaries;
-        (*w).total_documents = stats.total_documents;
-        (*w).total_images = stats.total_images;
-        (*w).total_music = stats.total_music;
-        (*w).total_other = stats.total_other;
-        (*w).total_videos = stats.total_videos;
+    /// Call `callback` on the scanning thread for every progress event
+    pub fn on_progress<F>(&self, callback: F)
+    where
+        F: FnMut(&ProgressEvent) + 'static,
+    {
+        self.progress.add_callback(Box::new(callback));
+    }
 
+    /// Minimum time between two progress updates
+    pub fn set_progress_interval(&self, interval: Duration) {
+        self.progress.set_interval(interval);
+    }
 
-        Ok(())
+    pub fn settings(&self) -> &ScanSettings {
+        &self.settings
     }
 
-    fn read_dir(&self, path: &str) -> u64 {
-        let mut total_dir_usage: u64 = 0;
+    pub fn analyze(&self) -> Scan {
+        let mut scan = Scan::new();
+        scan.mode = self.settings.mode;
+        scan.dirs = self.roots.clone();
+        scan.expected_bytes = expected_bytes(&self.roots);
 
-        let mut process_entries = |entries: ReadDir| {
-            for entry in entries {
-                let path = &entry.unwrap().path();
+        self.progress.emit(ProgressEvent::Started {
+            dirs: scan.dirs.clone(),
+            expected_bytes: scan.expected_bytes,
+        });
 
-                if path.is_dir() && !self.should_skip(&path) {
-                    if !self.settings.hidden && is_hidden(path) {
-                        continue;
-                    }
-                    let path_str = path.to_str().unwrap();
-                    // let new_child = node.push_child(path_str, true, 0);
+        for root in self.roots.iter() {
+            if self.settings.one_filesystem {
+                self.root_dev.set(device_id(root));
+            }
 
-                    // let node = FileTreeNode::new(String::from(path_str));
-                    total_dir_usage += self.read_dir(path_str);
+            if let Ok(meta) = metadata(root) {
+                self.first_visit(&meta);
+            }
 
-                    self.stats.borrow_mut().register_dir_usage(path, total_dir_usage);
-                } else if path.is_file() {
-                    if !self.settings.hidden && is_hidden(path) {
-                        continue;
-                    }
+            scan.tree.push(self.read_root(root));
+        }
+
+        let stats = self.stats.borrow();
 
-                    let mut w = self.scan_results.write();
+        scan.current_file = None;
+        scan.total_bytes = self.total_bytes.get();
+        scan.developer_dirs = stats.developer_dirs.clone();
+        scan.largest_files = stats.largest_files.to_vec();
+        scan.largest_dirs = stats.largest_dirs.to_vec();
+        scan.errors = stats.errors.clone();
+        scan.broken_symlinks = stats.broken_symlinks.clone();
+        scan.junk = stats.junk.clone();
+        scan.exclusions = self.filter.exclusion_counts();
+        scan.completed_at = Some(Local::now());
+        scan.num_files = stats.num_files;
+        scan.num_dirs = stats.num_dirs;
+        scan.dev_total_usage = stats.dev_total_usage;
+        scan.total_archives = stats.total_archives;
+        scan.total_binaries = stats.total_binaries;
+        scan.total_documents = stats.total_documents;
+        scan.total_images = stats.total_images;
+        scan.total_music = stats.total_music;
+        scan.total_other = stats.total_other;
+        scan.total_videos = stats.total_videos;
+
+        let last_root = scan.dirs.last().cloned().unwrap_or_default();
+        self.progress.finish(&last_root, scan.total_bytes, scan.num_files);
+
+        scan
+    }
+
+    /// Walk `path`, returning its directory tree
+    fn read_dir(&self, path: &Path) -> FileTreeNode {
+        let mut node = FileTreeNode::new(path.to_path_buf(), false, 0);
 
-                    (*w).current_file = Some(path.to_str().unwrap().to_string());
+        self.enter_dir(path);
 
-                    match metadata(path) {
-                        Ok(meta) => {
-                            let len = meta.len();
+        match fs::read_dir(path) {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => self.visit(&entry.path(), &mut node),
+                        Err(e) => {
+                            node.empty = false;
+                            self.stats.borrow_mut().register_error(ScanError::new(path, ScanOp::ReadEntry, &e));
+                        }
+                    }
+                }
+            },
+            Err(e) => {
+                node.empty = false;
+                self.stats.borrow_mut().register_error(ScanError::new(path, ScanOp::ReadDir, &e));
+            }
+        }
+
+        self.filter.leave_dir(path);
+        self.register_empty_children(&node);
+
+        node
+    }
 
-                            total_dir_usage += len;
+    /// Walk a root, splitting its subdirectories between `settings.threads` workers
+    fn read_root(&self, root: &Path) -> FileTreeNode {
+        if self.settings.threads <= 1 {
+            return self.read_dir(root);
+        }
 
-                            (*w).total_bytes += len;
+        let mut node = FileTreeNode::new(root.to_path_buf(), false, 0);
+        let mut subdirs = vec![];
 
-                            self.total_bytes.set(self.total_bytes.get() + len);
+        self.enter_dir(root);
 
-                            let path_str = Some(path.to_str().unwrap().clone());
-                            // let new_child = node.push_child(path_str.unwrap(), true, len);
-                            // self.files.borrow_mut().push(new_child);
-                            self.stats
-                                .borrow_mut()
-                                .register_file(path_str.unwrap(), len, self.settings.nlargest, self.settings.largebytes);
+        match fs::read_dir(root) {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => {
+                            let path = entry.path();
+                            if !self.should_visit(&path) {
+                                node.empty = false;
+                                continue;
+                            }
+
+                            if self.should_walk(&path) {
+                                subdirs.push(path);
+                            } else {
+                                node.empty = false;
+                                self.visit_file(&path, &mut node);
+                            }
                         },
-                        Err(e) => println!("Unable to read file {} - {}", path.to_str().unwrap(), e)
+                        Err(e) => {
+                            node.empty = false;
+                            self.stats.borrow_mut().register_error(ScanError::new(root, ScanOp::ReadEntry, &e));
+                        }
                     }
                 }
+            },
+            Err(e) => {
+                node.empty = false;
+                self.stats.borrow_mut().register_error(ScanError::new(root, ScanOp::ReadDir, &e));
             }
+        }
 
-        };
+        for child in self.read_dirs_parallel(subdirs) {
+            self.add_child(&mut node, child);
+        }
 
-        match fs::read_dir(path) {
-            Ok(entries) => {
-                process_entries(entries);
+        self.filter.leave_dir(root);
+        self.register_empty_children(&node);
+
+        node
+    }
+
+    /// Walks `dirs` on worker threads and merges their stats into this analyzer.
+    /// Progress from the workers is combined and re-emitted from the calling thread.
+    fn read_dirs_parallel(&self, dirs: Vec<PathBuf>) -> Vec<FileTreeNode> {
+        let threads = self.settings.threads.min(dirs.len()).max(1);
+        let queue = Mutex::new(dirs.into_iter().enumerate().rev().collect::<Vec<_>>());
+        let (tx, rx) = channel::<(usize, PathBuf, u64, u64)>();
+
+        let base_bytes = self.total_bytes.get();
+        let base_files = self.stats.borrow().num_files;
+
+        let results = thread::scope(|s| {
+            let mut handles = vec![];
+
+            for id in 0..threads {
+                let queue = &queue;
+                let tx = tx.clone();
+                let worker = Worker {
+                    stats: self.stats.borrow().fork(),
+                    filter: self.filter.fork(),
+                    settings: self.settings.clone(),
+                    root_dev: self.root_dev.get(),
+                    seen: self.seen.clone(),
+                    interval: self.progress.interval(),
+                };
+
+                handles.push(s.spawn(move || worker.run(id, queue, tx)));
+            }
+            drop(tx);
+
+            let mut latest = vec![(0, 0); threads];
+            for (id, current_file, bytes, files) in rx {
+                latest[id] = (bytes, files);
+
+                let bytes: u64 = latest.iter().map(|l| l.0).sum();
+                let files: u64 = latest.iter().map(|l| l.1).sum();
+                self.progress.update(&current_file, base_bytes + bytes, base_files + files);
+            }
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Scan worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut nodes = vec![];
+        for (worker_nodes, stats, total_bytes, filter) in results {
+            nodes.extend(worker_nodes);
+            self.total_bytes.set(self.total_bytes.get() + total_bytes);
+            self.stats.borrow_mut().merge(stats);
+            self.filter.merge_counts(&filter);
+        }
+
+        nodes.sort_by_key(|(i, _)| *i);
+        nodes.into_iter().map(|(_, node)| node).collect()
+    }
+
+    fn enter_dir(&self, path: &Path) {
+        if let Err(e) = self.filter.enter_dir(path) {
+            let err = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+            self.stats.borrow_mut().register_error(ScanError::new(&path.join(".gitignore"), ScanOp::ReadIgnoreFile, &err));
+        }
+    }
+
+    /// Adds the entry at `path` to `node`, walking into it if it's a directory
+    fn visit(&self, path: &Path, node: &mut FileTreeNode) {
+        if !self.should_visit(path) {
+            node.empty = false;
+            return;
+        }
+
+        if self.should_walk(path) {
+            let child = self.read_dir(path);
+            self.add_child(node, child);
+        } else {
+            node.empty = false;
+            self.visit_file(path, node);
+        }
+    }
+
+    fn add_child(&self, node: &mut FileTreeNode, child: FileTreeNode) {
+        node.len += self.settings.mode.weight(0) + child.len;
+        node.files += child.files;
+        node.empty &= child.empty;
+
+        self.stats.borrow_mut().register_dir_usage(&child.path, child.len);
+
+        node.children.push(child);
+    }
+
+    fn visit_file(&self, path: &Path, node: &mut FileTreeNode) {
+        if !path.is_file() {
+            return;
+        }
+
+        if !self.settings.hidden && is_hidden(path) {
+            return;
+        }
+
+        if self.filter.is_excluded(path, false) {
+            return;
+        }
+
+        match metadata(path) {
+            Ok(meta) => {
+                if !self.first_visit(&meta) {
+                    return;
+                }
+
+                let len = meta.len();
+
+                node.len += self.settings.mode.weight(len);
+                node.files += 1;
+
+                self.total_bytes.set(self.total_bytes.get() + len);
+
+                self.stats.borrow_mut().register_file(path, len);
+
+                let num_files = self.stats.borrow().num_files;
+                self.progress.update(path, self.total_bytes.get(), num_files);
             },
             Err(e) => {
-                println!("Unable to read directory {} - {}", path, e);
+                self.stats.borrow_mut().register_error(ScanError::new(path, ScanOp::Metadata, &e));
+            }
+        }
+    }
+
+    /// Records the empty subdirectories of a directory that isn't empty itself, so
+    /// only the top of each empty tree is listed and roots never are
+    fn register_empty_children(&self, node: &FileTreeNode) {
+        if node.empty {
+            return;
+        }
+
+        let mut stats = self.stats.borrow_mut();
+        for child in node.children.iter().filter(|child| child.empty) {
+            stats.register_junk(&child.path, JunkKind::EmptyDir, 0);
+        }
+    }
+
+    /// Whether `path` is a directory the walk should descend into
+    fn should_walk(&self, path: &Path) -> bool {
+        if !path.is_dir() || self.should_skip(path) {
+            return false;
+        }
+
+        if !self.settings.hidden && is_hidden(path) {
+            return false;
+        }
+
+        match metadata(path) {
+            Ok(meta) if !self.first_visit(&meta) => {
+                if path.is_symlink() && points_to_ancestor(path) {
+                    self.stats.borrow_mut().register_broken_symlink(BrokenSymlink::new(path, SymlinkIssue::Cycle));
+                }
+                false
+            },
+            _ => true,
+        }
+    }
+
+    /// Whether the walk should look at the entry at all. Symlinked files are
+    /// counted, symlinked directories are only walked into when following
+    /// symlinks, and links that can't be resolved are recorded either way.
+    fn should_visit(&self, path: &Path) -> bool {
+        if !path.is_symlink() {
+            return true;
+        }
+
+        if let Err(e) = metadata(path) {
+            let issue = match e.kind() {
+                io::ErrorKind::NotFound => SymlinkIssue::Dangling,
+                _ => SymlinkIssue::Broken,
+            };
+
+            let mut stats = self.stats.borrow_mut();
+            stats.register_broken_symlink(BrokenSymlink::new(path, issue));
+            if issue == SymlinkIssue::Dangling {
+                stats.register_junk(path, JunkKind::DanglingSymlink, 0);
             }
+            return false;
         }
 
-        total_dir_usage
+        self.settings.follow_symlinks || !path.is_dir()
+    }
+
+    /// Records the file or directory behind `meta` as counted, returning false if it
+    /// already was. Only tracked when following symlinks, since otherwise the walk
+    /// can't reach a directory twice.
+    fn first_visit(&self, meta: &Metadata) -> bool {
+        if !self.settings.follow_symlinks {
+            return true;
+        }
+
+        match file_id(meta) {
+            Some(id) => self.seen.lock().unwrap().insert(id),
+            None => true,
+        }
     }
 
     /*
@@ -213,13 +664,11 @@ impl<'a> Analyzer<'a> {
     */
 
     fn should_skip(&self, path: &Path) -> bool {
-        // Skip symlinks
-        if path.is_symlink() {
+        if self.settings.one_filesystem && self.root_dev.get().is_some() && device_id(path) != self.root_dev.get() {
             return true
         }
 
-        if self.ignore_pattern.matches(path.to_str().unwrap()) {
-            println!("Skipping ignored path: {:?}", path.to_str());
+        if self.filter.is_excluded(path, true) {
             return true
         }
 
@@ -235,112 +684,215 @@ impl<'a> Analyzer<'a> {
 
         return false
     }
+}
 
-    pub fn print_report(&self) {
-        println!("{}", "\n-- Usage Report --\n".bright_yellow());
-
-        let mut sys = System::new_all();
-        sys.refresh_all();
-
-        println!("{}", "Totals:".bright_green());
-        println!("  Disk usage: {}", bytes_to_human(self.total_bytes.get()));
-
-        println!("");
-
-        println!("{}", "Current disk usage:".bright_green());
-        for disk in sys.disks() {
-            let p = (disk.available_space() as f64 / disk.total_space() as f64) * 100.0;
-            println!(
-                "  {} ({} free ({:.2}%) , {} total)",
-                disk.name().to_str().unwrap(),
-                bytes_to_human(disk.available_space()),
-                p,
-                bytes_to_human(disk.total_space())
-            );
-        }
-
-        println!("");
+/// Walks part of a root on its own thread, see `Analyzer::read_dirs_parallel`
+struct Worker {
+    stats: AnalyzerStats,
+    filter: Filter,
+    settings: ScanSettings,
+    root_dev: Option<u64>,
+    seen: Arc<Mutex<HashSet<(u64, u64)>>>,
+    interval: Duration,
+}
 
-        let print_type = |type_name: &str, len: u64| {
-            println!("  {}: {}", type_name, bytes_to_human(len));
+impl Worker {
+    /// Takes directories off `queue` until it's empty, forwarding progress as
+    /// `(worker id, current file, bytes, files)`
+    fn run(
+        self,
+        id: usize,
+        queue: &Mutex<Vec<(usize, PathBuf)>>,
+        tx: Sender<(usize, PathBuf, u64, u64)>,
+    ) -> (Vec<(usize, FileTreeNode)>, AnalyzerStats, u64, Filter) {
+        let analyzer = Analyzer {
+            total_bytes: Cell::new(0),
+            roots: vec![],
+            stats: RefCell::new(self.stats),
+            filter: self.filter,
+            settings: self.settings,
+            root_dev: Cell::new(self.root_dev),
+            seen: self.seen,
+            progress: ProgressEmitter::new(),
         };
 
-        let stats = self.stats.borrow();
-        println!("{}", "File types:".bright_green());
-        print_type("Images", stats.total_images);
-        print_type("Videos", stats.total_videos);
-        print_type("Music", stats.total_music);
-        print_type("Documents", stats.total_documents);
-        print_type("Archives", stats.total_archives);
-        print_type("Binaries", stats.total_binaries);
-        print_type("Other", stats.total_other);
+        analyzer.set_progress_interval(self.interval);
+        analyzer.on_progress(move |event| {
+            if let ProgressEvent::Update { current_file, bytes, files } = event {
+                let _ = tx.send((id, current_file.clone(), *bytes, *files));
+            }
+        });
+
+        let mut nodes = vec![];
+        loop {
+            let next = queue.lock().unwrap().pop();
+            match next {
+                Some((i, dir)) => nodes.push((i, analyzer.read_dir(&dir))),
+                None => break,
+            }
+        }
 
-        println!("");
+        let Analyzer { total_bytes, stats, filter, .. } = analyzer;
 
-        println!("{}", "Top files:".bright_green());
-        self.stats.borrow().print_largest();
+        (nodes, stats.into_inner(), total_bytes.get(), filter)
     }
+}
 
-    pub fn prompt_delete(&self) {
-        let mut total_deleted: u64 = 0;
-
-        let selections = &[
-            "Keep",
-            "Exit",
-            "Delete (trash)",
-            "Delete (force)"
-        ];
-        for file in self.stats.borrow().get_largest() {
-            println!("");
-            let selection = Select::with_theme(&ColorfulTheme::default())
-                .with_prompt(format!("Delete {} ({})?", file.0, bytes_to_human(file.1)))
-                .default(0)
-                .items(&selections[..])
-                .interact_opt()
-                .unwrap();
-
-            if let Some(selection) = selection {
-                if selection == 1 {
-                    // Exit on exit
-                    break
-                }
+/// Whether the symlink at `path` points to a directory containing it
+fn points_to_ancestor(path: &Path) -> bool {
+    match (fs::canonicalize(path), path.parent().map(fs::canonicalize)) {
+        (Ok(target), Some(Ok(parent))) => parent.starts_with(target),
+        _ => false,
+    }
+}
 
-                if selection == 2 {
-                    println!("Deleting {}", file.0);
-                    match trash::delete(&file.0) {
-                        Ok(_) => {
-                            println!("Deleted!");
-                            total_deleted += file.1;
-                        },
-                        Err(e) => println!("Unable to delete: {}", e)
-                    }
-                } else if selection == 3 {
-                    println!("Deleting (force) {}", file.0);
-                    match fs::remove_file(&file.0) {
-                        Ok(_) => {
-                            println!("Deleted!");
-                            total_deleted += file.1;
-                        },
-                        Err(e) => println!("Unable to delete: {}", e)
-                    }
-                }
-            }
+/// Resolves the roots and drops any that are the same as, or nested inside,
+/// another root so nothing gets counted twice
+pub fn dedupe_roots(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut resolved: Vec<PathBuf> = dirs
+        .iter()
+        .map(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+        .collect();
+
+    // Parents sort before their children
+    resolved.sort();
+
+    let mut roots: Vec<PathBuf> = vec![];
+    for dir in resolved {
+        if !roots.iter().any(|root| dir.starts_with(root)) {
+            roots.push(dir);
         }
-
-        println!("");
-        println!("Reclaimed {} of disk space", bytes_to_human(total_deleted));
     }
+
+    roots
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use glob::Pattern;
 
+    use super::{dedupe_roots, Analyzer, ScanSettings, UsageMode};
+    use crate::{errors::SymlinkIssue, Scan};
+
     #[test]
     fn pattern_match() {
         let pattern = Pattern::new("**/node_modules").expect("Unable to parse ignore glob pattern");
 
         assert_eq!(pattern.matches("/Users/max/git/project/node_modules"), true);
     }
-}
 
+    #[cfg(unix)]
+    #[test]
+    fn follow_symlinks_counts_targets_once() {
+        use std::{fs, os::unix::fs::symlink};
+
+        let root = std::env::temp_dir().join(format!("disk-usage-symlinks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(root.join("data/file"), [0; 100]).unwrap();
+        symlink(root.join("data"), root.join("data-link")).unwrap();
+        symlink(&root, root.join("data/loop")).unwrap();
+        symlink(root.join("missing"), root.join("dangling")).unwrap();
+
+        let settings = ScanSettings::builder().dir(&root).follow_symlinks(true).build();
+        let scan = Analyzer::new(settings).unwrap().analyze();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(scan.total_bytes, 100);
+        assert_eq!(scan.num_files, 1);
+
+        let mut issues: Vec<SymlinkIssue> = scan.broken_symlinks.iter().map(|s| s.issue).collect();
+        issues.sort_by_key(|issue| *issue as u8);
+        assert_eq!(issues, vec![SymlinkIssue::Dangling, SymlinkIssue::Cycle]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_files_counted_without_following() {
+        use std::{fs, os::unix::fs::symlink};
+
+        let root = std::env::temp_dir().join(format!("disk-usage-file-links-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(root.join("data/file"), [0; 100]).unwrap();
+        symlink(root.join("data/file"), root.join("file-link")).unwrap();
+        symlink(root.join("data"), root.join("data-link")).unwrap();
+
+        let scan = Analyzer::new(ScanSettings::builder().dir(&root).build()).unwrap().analyze();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        // The linked file is counted again, the linked directory isn't walked
+        assert_eq!(scan.total_bytes, 200);
+        assert_eq!(scan.num_files, 2);
+    }
+
+    #[test]
+    fn parallel_scan_matches_single_threaded() {
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("disk-usage-parallel-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for i in 0..12 {
+            let dir = root.join(format!("dir{}/nested/deeper", i));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("data.bin"), vec![0; 1000 * (i + 1)]).unwrap();
+            fs::write(root.join(format!("dir{}/notes.log", i)), [0; 10]).unwrap();
+            fs::write(root.join(format!("dir{}/nested/empty.txt", i)), []).unwrap();
+        }
+        fs::create_dir_all(root.join("dir3/node_modules/pkg")).unwrap();
+        fs::write(root.join("dir3/node_modules/pkg/index.js"), [0; 300]).unwrap();
+        fs::create_dir_all(root.join("empty/inside")).unwrap();
+        fs::write(root.join("top.bin"), [0; 50]).unwrap();
+
+        let scan_with = |threads, mode| {
+            let settings = ScanSettings::builder()
+                .dir(&root)
+                .ignore("*.log")
+                .nlargest(100)
+                .largebytes(0)
+                .threads(threads)
+                .mode(mode)
+                .build();
+            Analyzer::new(settings).unwrap().analyze()
+        };
+
+        for mode in [UsageMode::Bytes, UsageMode::Files] {
+            let single = scan_with(1, mode);
+            let parallel = scan_with(4, mode);
+
+            assert_eq!(parallel.total_bytes, single.total_bytes);
+            assert_eq!(parallel.num_files, single.num_files);
+            assert_eq!(parallel.num_dirs, single.num_dirs);
+            assert_eq!(parallel.root_usage(), single.root_usage());
+            assert_eq!(parallel.largest_files, single.largest_files);
+            assert_eq!(parallel.largest_dirs, single.largest_dirs);
+            assert_eq!(parallel.developer_dirs.len(), single.developer_dirs.len());
+            assert_eq!(parallel.exclusions, single.exclusions);
+            assert_eq!(parallel.junk.len(), single.junk.len());
+
+            let children = |scan: &Scan| scan.tree[0].children.iter().map(|c| c.path.clone()).collect::<Vec<_>>();
+            assert_eq!(children(&parallel), children(&single));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dedupe_nested_roots() {
+        let dirs = vec![
+            PathBuf::from("/nonexistent/var/lib"),
+            PathBuf::from("/nonexistent/home"),
+            PathBuf::from("/nonexistent/var"),
+            PathBuf::from("/nonexistent/home"),
+        ];
+
+        assert_eq!(dedupe_roots(&dirs), vec![
+            PathBuf::from("/nonexistent/home"),
+            PathBuf::from("/nonexistent/var"),
+        ]);
+    }
+}
@@ -1,29 +1,116 @@
 use std::fs;
+use std::path::{Path, PathBuf};
 
 use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{channel, Receiver};
 use std::{cell::RefCell, sync::Arc, thread};
 
-use chrono::{Local, DateTime};
 use dirs::home_dir;
 use egui::{Vec2, Frame, Context, ProgressBar};
 use egui_extras::{Size, TableBuilder};
 
 use eframe::egui;
 use egui::{
-    mutex::RwLock, vec2, Align2, Button, CentralPanel, Layout, ScrollArea, SidePanel,
+    mutex::RwLock, vec2, Align2, Button, CentralPanel, Checkbox, CollapsingHeader, DragValue, Layout, ScrollArea,
     TopBottomPanel, Ui, Visuals, Window,
 };
 use rfd::{AsyncFileDialog, FileDialog};
 
-use crate::analyzer::{Analyzer, ScanSettings};
-use crate::{utils::bytes_to_human};
+use disk_usage::audit::{AuditEntry, AuditLog};
+use disk_usage::compress::{archive_dir, compress_file, Compression, Shrunk};
+use disk_usage::delete::{delete_with_progress, ActionSettings, DeleteMode, RemoveProgress};
+use disk_usage::errors::summarize_errors;
+use disk_usage::filter::Filter;
+use disk_usage::junk::{summarize_junk, JunkKind};
+use disk_usage::plan::{reclaimed_bytes, CleanupPlan, PlanItem, PlanResult};
+use disk_usage::progress::{format_duration, ProgressEvent};
+use disk_usage::protect::ProtectedPaths;
+use disk_usage::relocate::{free_space, relocate, Relocated};
+use disk_usage::restore::{self, trashed_items, TrashedItem};
+use disk_usage::utils::{bytes_to_human, format_usage, parse_size, SizeUnits};
+use disk_usage::{Analyzer, Scan, UsageMode};
+
+use crate::config::{CheckConfig, Config, Theme};
 
 #[derive(PartialEq, Clone)]
 #[repr(u8)]
 enum CurrentTab {
     LargeFiles = 0,
     Recs = 1,
-    Summary = 2
+    Summary = 2,
+    Errors = 3,
+    LargeDirs = 4
+}
+
+/// Deletions running in the background, like a cleanup plan
+#[derive(Default)]
+struct DeletionRun {
+    scan_id: String,
+    /// Each item's usage in the scan's mode, taken out of the results once it's gone
+    usage: Vec<u64>,
+    results: Vec<PlanResult>,
+    /// How far removing the current directory tree has got
+    progress: Option<RemoveProgress>,
+    running: bool,
+    /// Whether the results have been applied to the scan and the plan
+    applied: bool,
+    /// Nothing was deleted, so there's nothing to apply
+    dry_run: bool,
+}
+
+impl DeletionRun {
+    fn progress_text(&self, units: SizeUnits) -> String {
+        match &self.progress {
+            Some(progress) => format!(
+                "Deleting {}: removed {} files ({})",
+                progress.current.display(),
+                progress.files,
+                bytes_to_human(progress.bytes, units)
+            ),
+            None => format!("Deleting {} of {} items", self.results.len() + 1, self.usage.len()),
+        }
+    }
+}
+
+/// Compressing a file or archiving a directory in the background
+#[derive(Default)]
+struct ShrinkRun {
+    scan_id: String,
+    path: PathBuf,
+    result: Option<Result<Shrunk, String>>,
+    running: bool,
+    /// Whether the result has been applied to the scan
+    applied: bool,
+    /// Nothing was written or removed, so there's nothing to apply
+    dry_run: bool,
+}
+
+/// A move picked in the results, waiting to be confirmed
+#[derive(Clone)]
+struct RelocateRequest {
+    path: PathBuf,
+    /// Its usage in the scan's mode
+    usage: u64,
+    destination: PathBuf,
+    /// Free space on the destination's filesystem, looked up when it was picked
+    free: Option<u64>,
+    leave_symlink: bool,
+}
+
+/// Moving a path somewhere else in the background
+#[derive(Default)]
+struct RelocateRun {
+    scan_id: String,
+    path: PathBuf,
+    usage: u64,
+    /// Bytes copied so far, when moving to another filesystem
+    copied: u64,
+    result: Option<Result<Relocated, String>>,
+    running: bool,
+    /// Whether the result has been applied to the scan
+    applied: bool,
+    /// Nothing was moved, so there's nothing to apply
+    dry_run: bool,
 }
 
 #[derive(Clone)]
@@ -31,73 +118,303 @@ pub struct UiState {
     show_delete_confirm: bool,
     show_developer_delete_confirm: bool,
     show_settings: RefCell<bool>,
-    file_to_delete: Option<(String, bool)>,
+    show_history: RefCell<bool>,
+    history: RefCell<Vec<AuditEntry>>,
+    history_error: RefCell<Option<String>>,
+    show_trash: RefCell<bool>,
+    trash_items: RefCell<Vec<TrashedItem>>,
+    trash_error: RefCell<Option<String>>,
+    item_to_purge: RefCell<Option<TrashedItem>>,
+    /// What the last deletion moved to the trash, for undo
+    last_trashed: Vec<PathBuf>,
+    /// Whether operations started from now on only report what they would do
+    dry_run: bool,
+    file_to_delete: Option<(PathBuf, u64, DeleteMode)>,
+    junk_to_delete: Option<JunkKind>,
+    plan: CleanupPlan,
+    show_plan: RefCell<bool>,
+    show_plan_confirm: bool,
+    plan_error: Option<String>,
+    plan_run: Arc<RwLock<DeletionRun>>,
+    /// Deletions picked outside the plan
+    deletion_run: Arc<RwLock<DeletionRun>>,
+    /// Why the last deletion, compression or move picked couldn't start
+    run_error: Option<String>,
+    shrink_run: Arc<RwLock<ShrinkRun>>,
+    relocate_request: Option<RelocateRequest>,
+    relocate_run: Arc<RwLock<RelocateRun>>,
+    /// What's been typed to confirm deleting a protected path
+    protected_confirm_text: String,
+    show_leaf_dirs: bool,
     current_tab: CurrentTab,
-    setting_root_dir: RefCell<String>,
+    setting_roots: RefCell<Vec<String>>,
     setting_ignore_glob: RefCell<String>,
+    setting_exclude_files: RefCell<String>,
+    setting_use_gitignore: RefCell<bool>,
+    settings_error: RefCell<Option<String>>,
     setting_developer_cache_dirs: RefCell<String>,
     setting_hidden: RefCell<bool>,
     setting_nlargest: RefCell<u64>,
-    setting_largebytes: RefCell<u64>
-}
-
-type LargeFile = (String, u64);
-pub struct Scan {
-    pub dir: String,
-    pub started_at: DateTime<Local>,
-    pub completed_at: Option<DateTime<Local>>,
-    pub current_file: Option<String>,
-    pub total_bytes: u64,
-    pub largest_files: Box<Vec<LargeFile>>,
-    pub num_files: u64,
-    pub total_music: u64,
-    pub total_images: u64,
-    pub total_videos: u64,
-    pub total_documents: u64,
-    pub total_binaries: u64,
-    pub total_archives: u64,
-    pub total_other: u64,
-    pub dev_total_usage: u64,
-    pub developer_dirs: Vec<LargeFile>
-}
-
-impl Scan {
-    pub fn clear(&mut self) {
-        self.dir = String::from("");
-        self.started_at = Local::now();
-        self.completed_at = None;
-        self.current_file = None;
-        self.total_bytes = 0;
-        self.largest_files = Box::new(vec![]);
-        self.total_music = 0;
-        self.total_images = 0;
-        self.total_videos = 0;
-        self.total_documents = 0;
-        self.total_archives= 0;
-        self.total_other = 0;
-        self.dev_total_usage = 0;
-        self.developer_dirs = vec![];
+    setting_largebytes: RefCell<u64>,
+    setting_largebytes_text: RefCell<String>,
+    setting_follow_symlinks: RefCell<bool>,
+    setting_one_filesystem: RefCell<bool>,
+    setting_threads: RefCell<usize>,
+    setting_protected_paths: RefCell<String>,
+    /// The protected paths runs started from now on refuse, updated as they're edited
+    protected: RefCell<ProtectedPaths>,
+    setting_mode: RefCell<UsageMode>,
+    setting_units: RefCell<SizeUnits>,
+    setting_theme: RefCell<Theme>,
+    /// Not editable here, kept so saving the settings doesn't drop them
+    check: CheckConfig,
+}
+
+/// A size for a text field, in the current units when that's exact
+fn size_text(bytes: u64, units: SizeUnits) -> String {
+    let text = bytes_to_human(bytes, units);
+
+    match parse_size(&text) {
+        Ok(parsed) if parsed == bytes => text,
+        _ => bytes.to_string(),
+    }
+}
+
+/// Splits a multiline text field into its non-empty lines
+fn setting_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl UiState {
+    fn from_config(config: &Config) -> UiState {
+        let lines = |items: Vec<String>| items.join("\n");
+
+        UiState {
+            show_delete_confirm: false,
+            show_developer_delete_confirm: false,
+            show_settings: RefCell::new(false),
+            show_history: RefCell::new(false),
+            history: RefCell::new(vec![]),
+            history_error: RefCell::new(None),
+            show_trash: RefCell::new(false),
+            trash_items: RefCell::new(vec![]),
+            trash_error: RefCell::new(None),
+            item_to_purge: RefCell::new(None),
+            dry_run: false,
+            last_trashed: vec![],
+            file_to_delete: None,
+            junk_to_delete: None,
+            plan: CleanupPlan::default(),
+            show_plan: RefCell::new(false),
+            show_plan_confirm: false,
+            plan_error: None,
+            plan_run: Arc::new(RwLock::new(DeletionRun::default())),
+            deletion_run: Arc::new(RwLock::new(DeletionRun::default())),
+            run_error: None,
+            shrink_run: Arc::new(RwLock::new(ShrinkRun::default())),
+            relocate_request: None,
+            relocate_run: Arc::new(RwLock::new(RelocateRun::default())),
+            protected_confirm_text: String::new(),
+            show_leaf_dirs: false,
+            current_tab: CurrentTab::Summary,
+            setting_developer_cache_dirs: RefCell::new(lines(config.developer_patterns.clone())),
+            setting_ignore_glob: RefCell::new(lines(config.ignore.clone())),
+            setting_exclude_files: RefCell::new(lines(
+                config.exclude_files.iter().map(|f| f.to_string_lossy().to_string()).collect()
+            )),
+            setting_use_gitignore: RefCell::new(config.use_gitignore),
+            settings_error: RefCell::new(None),
+            setting_hidden: RefCell::new(config.hidden),
+            setting_largebytes: RefCell::new(config.largebytes),
+            setting_largebytes_text: RefCell::new(size_text(config.largebytes, config.units)),
+            setting_follow_symlinks: RefCell::new(config.follow_symlinks),
+            setting_one_filesystem: RefCell::new(config.one_filesystem),
+            setting_threads: RefCell::new(config.threads.max(1)),
+            setting_protected_paths: RefCell::new(lines(
+                config.protected_paths.iter().map(|p| p.to_string_lossy().to_string()).collect()
+            )),
+            protected: RefCell::new(ProtectedPaths::new(&config.protected_paths)),
+            setting_nlargest: RefCell::new(config.nlargest),
+            setting_mode: RefCell::new(config.mode),
+            setting_units: RefCell::new(config.units),
+            setting_roots: RefCell::new(
+                config.roots.iter().map(|r| r.to_string_lossy().to_string()).collect()
+            ),
+            setting_theme: RefCell::new(config.theme),
+            check: config.check.clone(),
+        }
+    }
+
+    fn to_config(&self, window_size: Vec2) -> Config {
+        Config {
+            roots: self.roots(),
+            ignore: self.ignore_patterns(),
+            exclude_files: self.exclude_files(),
+            use_gitignore: *self.setting_use_gitignore.borrow(),
+            developer_patterns: setting_lines(&self.setting_developer_cache_dirs.borrow()),
+            nlargest: *self.setting_nlargest.borrow(),
+            largebytes: *self.setting_largebytes.borrow(),
+            hidden: *self.setting_hidden.borrow(),
+            follow_symlinks: *self.setting_follow_symlinks.borrow(),
+            one_filesystem: *self.setting_one_filesystem.borrow(),
+            threads: *self.setting_threads.borrow(),
+            protected_paths: self.protected_paths(),
+            mode: *self.setting_mode.borrow(),
+            units: *self.setting_units.borrow(),
+            theme: *self.setting_theme.borrow(),
+            window_size: [window_size.x, window_size.y],
+            check: self.check.clone(),
+        }
+    }
+
+    fn roots(&self) -> Vec<PathBuf> {
+        self.setting_roots
+            .borrow()
+            .iter()
+            .map(|r| r.trim())
+            .filter(|r| !r.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    fn ignore_patterns(&self) -> Vec<String> {
+        setting_lines(&self.setting_ignore_glob.borrow())
+    }
+
+    fn exclude_files(&self) -> Vec<PathBuf> {
+        setting_lines(&self.setting_exclude_files.borrow())
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    fn protected_paths(&self) -> Vec<PathBuf> {
+        setting_lines(&self.setting_protected_paths.borrow())
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    fn units(&self) -> SizeUnits {
+        *self.setting_units.borrow()
+    }
+
+    fn is_protected(&self, path: &Path) -> bool {
+        self.protected.borrow().is_protected(path)
+    }
+
+    /// What a deletion, compression or move started now runs with
+    fn action_settings(&self) -> ActionSettings {
+        ActionSettings {
+            dry_run: self.dry_run,
+            protected: self.protected.borrow().clone(),
+            units: self.units(),
+        }
+    }
+
+    /// Reads the audit log, newest entries first
+    fn load_history(&self) {
+        let (entries, error) = match AuditLog::default_location().map(|log| log.read()) {
+            Some(Ok(entries)) => (entries, None),
+            Some(Err(e)) => (vec![], Some(format!("Unable to read audit log: {}", e))),
+            None => (vec![], None),
+        };
+
+        *self.history_error.borrow_mut() = error;
+        *self.history.borrow_mut() = entries.into_iter().rev().collect();
+    }
+
+    /// Lists what this app moved to the trash, newest first
+    fn load_trash(&self) {
+        match trashed_items() {
+            Ok(items) => *self.trash_items.borrow_mut() = items,
+            Err(e) => {
+                *self.trash_items.borrow_mut() = vec![];
+                *self.trash_error.borrow_mut() = Some(e);
+            }
+        }
+    }
+
+    /// Checks the settings that can be invalid, storing the error to show in the
+    /// settings window. A valid large file size is copied to `setting_largebytes`.
+    fn validate_settings(&self) -> bool {
+        let mut error = self.settings_error.borrow_mut();
+
+        *error = match parse_size(&self.setting_largebytes_text.borrow()) {
+            Ok(largebytes) => {
+                *self.setting_largebytes.borrow_mut() = largebytes;
+                None
+            }
+            Err(e) => Some(format!("Large file size: {}", e)),
+        };
+
+        if error.is_none() {
+            let result = Filter::new(&self.ignore_patterns(), &self.exclude_files(), *self.setting_use_gitignore.borrow());
+            *error = result.err().map(|e| e.to_string());
+        }
+
+        error.is_none()
     }
 }
 
 pub struct App {
+    config_path: Option<PathBuf>,
+    window_size: RefCell<Vec2>,
     scan_results: Arc<RwLock<Scan>>,
+    progress_events: RefCell<Option<Receiver<ProgressEvent>>>,
     ui_state: RefCell<UiState>,
     scanning: Arc<RwLock<bool>>
 }
 
 impl eframe::App for App {
+    fn on_exit(&mut self, _gl: &eframe::glow::Context) {
+        self.save_config();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.set_visuals(Visuals::dark());
+        match *self.ui_state.borrow().setting_theme.borrow() {
+            Theme::Dark => ctx.set_visuals(Visuals::dark()),
+            Theme::Light => ctx.set_visuals(Visuals::light()),
+        }
+
+        *self.window_size.borrow_mut() = ctx.input().screen_rect().size();
+
+        if let Some(rx) = &*self.progress_events.borrow() {
+            let mut w = self.scan_results.write();
+            for event in rx.try_iter() {
+                w.apply(event);
+            }
+        }
+
+        self.apply_deletions();
+        self.apply_shrink();
+        self.apply_relocate();
 
         let r = self.scan_results.read();
         let scan_results = &*r;
+        let units = self.ui_state.borrow().units();
 
         egui::CentralPanel::default().show(ctx, |ui| {
             if *self.ui_state.borrow().show_settings.borrow() {
                 render_settings(ui, ctx, scan_results, &self.ui_state);
             }
 
+            if *self.ui_state.borrow().show_history.borrow() {
+                render_history(ui, ctx, &self.ui_state);
+            }
+
+            if *self.ui_state.borrow().show_trash.borrow() {
+                render_trash(ui, ctx, &self.ui_state);
+            }
+
+            if *self.ui_state.borrow().show_plan.borrow() {
+                render_plan(ui, ctx, &self.ui_state);
+            }
+
             TopBottomPanel::top("my_panel")
             .frame(Frame::group(ui.style()).inner_margin(Vec2::new(8.0, 16.0)))
             .show(ctx, |ui| {
@@ -107,24 +424,31 @@ impl eframe::App for App {
                 render_scan_control(ui, ctx, &self, &self.ui_state);
 
                 if let Some(current_file) = &scan_results.current_file {
-                    let duration = Local::now().signed_duration_since(scan_results.started_at);
-                    let duration_str = format!(
-                        "{}:{}:{}",
-                        duration.num_hours(),
-                        duration.num_minutes(),
-                        duration.num_seconds()
-                    );
-                    ui.label(format!("Scanning {}", scan_results.dir));
+                    let progress = scan_results.progress();
+
+                    ui.label(format!("Scanning {}", scan_results.dirs_label()));
+                    if let Some(fraction) = progress.fraction() {
+                        ui.add(ProgressBar::new(fraction).show_percentage());
+                    }
                     ui.label(format!(
                         "Elapsed time: {}",
-                        duration_str
+                        format_duration(progress.elapsed)
+                    ));
+                    if let Some(eta) = progress.eta() {
+                        ui.label(format!("Estimated time left: {}", format_duration(eta)));
+                    }
+                    ui.label(format!(
+                        "Usage (seen): {} in {} files",
+                        bytes_to_human(scan_results.total_bytes, units),
+                        scan_results.num_files
                     ));
                     ui.label(format!(
-                        "Usage (seen): {}",
-                        bytes_to_human(scan_results.total_bytes)
+                        "Throughput: {:.0} files/s, {}/s",
+                        progress.files_per_sec(),
+                        bytes_to_human(progress.bytes_per_sec() as u64, units)
                     ));
 
-                    ui.label(current_file.clone());
+                    ui.label(current_file.display().to_string());
 
                     // Still scanning, so repaint
                     if *self.scanning.read() {
@@ -132,7 +456,7 @@ impl eframe::App for App {
                     }
                 } else {
                     if let Some(completed_at) = scan_results.completed_at {
-                        ui.label(format!("Scanned {}", scan_results.dir));
+                        ui.label(format!("Scanned {}", scan_results.dirs_label()));
                         let duration = completed_at.signed_duration_since(scan_results.started_at);
                         let duration_str = format!(
                             "{}:{}:{}",
@@ -146,8 +470,10 @@ impl eframe::App for App {
                             duration_str
                         ));
                         ui.label(format!(
-                            "Total usage: {}",
-                            bytes_to_human(scan_results.total_bytes)
+                            "Total usage: {} in {} files and {} directories",
+                            bytes_to_human(scan_results.total_bytes, units),
+                            scan_results.num_files,
+                            scan_results.num_dirs
                         ));
                     }
 
@@ -158,28 +484,61 @@ impl eframe::App for App {
     }
 }
 
+impl App {
+    fn save_config(&self) {
+        let config = self.ui_state.borrow().to_config(*self.window_size.borrow());
+
+        if let Err(e) = config.save(self.config_path.as_deref()) {
+            println!("{}", e);
+        }
+    }
+}
+
 fn render_scan_control(ui: &mut Ui, ctx: &egui::Context, app: &App, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
     let scan_button = Button::new("Scan");
 
     {
         let state = ui_state.borrow();
-        let mut dir = state.setting_root_dir.borrow_mut();
+        let mut roots = state.setting_roots.borrow_mut();
+        let mut remove = None;
 
-        ui.horizontal(|ui| {
-            ui.text_edit_singleline(&mut *dir);
-            if ui.button("...").clicked() {
-                let f = FileDialog::new()
-                    .set_directory(&*dir)
-                    .pick_folder();
+        for (i, dir) in roots.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(dir);
+                if ui.button("...").clicked() {
+                    let f = FileDialog::new()
+                        .set_directory(&*dir)
+                        .pick_folder();
+
+                    if let Some(folder) = f {
+                        println!("Got folder: {}", folder.display());
 
-                if let Some(folder) = f {
-                    println!("Got folder: {:?}", folder.to_str());
+                        *dir = folder.to_string_lossy().to_string();
+                    }
+                    // let data = file.unwrap().read().await;
 
-                    *dir = String::from(folder.to_str().unwrap());
                 }
-                // let data = file.unwrap().read().await;
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
 
-            }
+        if let Some(i) = remove {
+            roots.remove(i);
+        }
+
+        if ui.button("Add root").clicked() {
+            roots.push(String::from(""));
+        }
+
+        let mut mode = state.setting_mode.borrow_mut();
+
+        ui.horizontal(|ui| {
+            ui.label("Rank by");
+            ui.selectable_value(&mut *mode, UsageMode::Bytes, "Bytes");
+            ui.selectable_value(&mut *mode, UsageMode::Files, "File count");
         });
     }
 
@@ -190,6 +549,9 @@ fn render_scan_control(ui: &mut Ui, ctx: &egui::Context, app: &App, ui_state: &R
     {
         app.start_scan();
     }
+    if let Some(error) = &*ui_state.borrow().settings_error.borrow() {
+        ui.colored_label(egui::Color32::RED, error);
+    }
     let stop_button = Button::new("Stop");
     if ui
         .add_enabled(*app.scanning.write(), stop_button)
@@ -197,40 +559,218 @@ fn render_scan_control(ui: &mut Ui, ctx: &egui::Context, app: &App, ui_state: &R
     {
         app.stop_scan();
     }
-            /*
-            SidePanel::left("my_left_panel")
-            .frame(Frame::group(ui.style()).inner_margin(Vec2::new(8.0, 8.0)))
-            .show(ctx, |ui| {
-                ui.columns(2, |cols| {
-                });
 
-                if ui.button("Settings").clicked() {
-                    let state = self.ui_state.borrow();
-                    let mut s = state.show_settings.borrow_mut();
-                    *s = true;
-                }
-            });
-            */
-}
+    if ui.button("Settings").clicked() {
+        let state = ui_state.borrow();
+        let mut s = state.show_settings.borrow_mut();
+        *s = true;
+    }
 
-fn delete_file(path: String, force: bool) {
-    if force {
-        match fs::remove_file(&path) {
-            Ok(_) => {
-                println!("Deleted!");
-                // total_deleted += file.1;
-            }
-            Err(e) => println!("Unable to delete: {}", e),
+    if ui.button("History").clicked() {
+        let state = ui_state.borrow();
+        state.load_history();
+        *state.show_history.borrow_mut() = true;
+    }
+
+    let plan_label = format!("Cleanup plan ({})", ui_state.borrow().plan.items.len());
+    if ui.button(plan_label).clicked() {
+        *ui_state.borrow().show_plan.borrow_mut() = true;
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("Trash").clicked() {
+            let state = ui_state.borrow();
+            *state.trash_error.borrow_mut() = None;
+            state.load_trash();
+            *state.show_trash.borrow_mut() = true;
         }
-    } else {
-        match trash::delete(&path) {
-            Ok(_) => {
-                println!("Deleted!");
-                // total_deleted += file.1;
-            }
-            Err(e) => println!("Unable to delete: {}", e),
+
+        ui.checkbox(&mut ui_state.borrow_mut().dry_run, "Dry run")
+            .on_hover_text("Only report what deleting, compressing, moving, restoring or purging would do");
+
+        let undo_button = Button::new("Undo");
+        if ui
+            .add_enabled(!ui_state.borrow().last_trashed.is_empty(), undo_button)
+            .on_hover_text("Restore what the last deletion moved to the trash")
+            .clicked()
+        {
+            let mut state = ui_state.borrow_mut();
+            let paths = std::mem::take(&mut state.last_trashed);
+            *state.trash_error.borrow_mut() = restore::undo(&paths, "", state.dry_run).err();
+        }
+    });
+
+    if let Some(error) = &*ui_state.borrow().trash_error.borrow() {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+    if let Some(error) = &ui_state.borrow().run_error {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+
+    let run = ui_state.borrow().deletion_run.clone();
+    let r = run.read();
+    if r.running {
+        ui.label(r.progress_text(units));
+        ctx.request_repaint();
+    }
+    for result in r.results.iter() {
+        if let Err(e) = &result.result {
+            ui.colored_label(egui::Color32::RED, format!("Unable to delete {}: {}", result.item.path.display(), e));
+        }
+    }
+
+    let run = ui_state.borrow().shrink_run.clone();
+    let r = run.read();
+    if r.running {
+        ui.label(format!("Compressing {}", r.path.display()));
+        ctx.request_repaint();
+    }
+    match &r.result {
+        Some(Ok(shrunk)) if r.dry_run => {
+            ui.label(format!("Would compress {}", shrunk.summary(units)));
+        }
+        Some(Ok(shrunk)) => {
+            ui.label(format!("Compressed {}", shrunk.summary(units)));
+        }
+        Some(Err(e)) => {
+            ui.colored_label(egui::Color32::RED, format!("Unable to compress {}: {}", r.path.display(), e));
+        }
+        None => {}
+    }
+
+    let run = ui_state.borrow().relocate_run.clone();
+    let r = run.read();
+    if r.running {
+        ui.label(format!("Moving {}: copied {}", r.path.display(), bytes_to_human(r.copied, units)));
+        ctx.request_repaint();
+    }
+    match &r.result {
+        Some(Ok(relocated)) => {
+            let verb = if r.dry_run { "Would move" } else { "Moved" };
+            ui.label(format!("{} {} to {}", verb, relocated.path.display(), relocated.destination.display()));
         }
+        Some(Err(e)) => {
+            ui.colored_label(egui::Color32::RED, format!("Unable to move {}: {}", r.path.display(), e));
+        }
+        None => {}
+    }
+}
+
+/// Moves the path in `request` on a background thread. Refused while `run` is still going.
+fn start_relocate(
+    request: RelocateRequest,
+    scan_id: &str,
+    settings: ActionSettings,
+    run: Arc<RwLock<RelocateRun>>,
+) -> Result<(), String> {
+    if run.read().running {
+        return Err(String::from("Still moving, try again when it's done"));
+    }
+
+    *run.write() = RelocateRun {
+        scan_id: String::from(scan_id),
+        path: request.path.clone(),
+        usage: request.usage,
+        running: true,
+        dry_run: settings.dry_run,
+        ..Default::default()
+    };
+
+    let scan_id = String::from(scan_id);
+    thread::spawn(move || {
+        let result = relocate(&request.path, &request.destination, request.leave_symlink, &scan_id, &settings, &mut |copied| {
+            run.write().copied = copied;
+        });
+
+        let mut r = run.write();
+        r.result = Some(result);
+        r.running = false;
+    });
+
+    Ok(())
+}
+
+/// Compresses the file at `path` with `compression`, or archives the directory
+/// when there's none, on a background thread. Refused while `run` is still going.
+fn start_shrink(
+    path: &Path,
+    compression: Option<Compression>,
+    scan_id: &str,
+    settings: ActionSettings,
+    run: Arc<RwLock<ShrinkRun>>,
+) -> Result<(), String> {
+    if run.read().running {
+        return Err(String::from("Still compressing, try again when it's done"));
+    }
+
+    *run.write() = ShrinkRun {
+        scan_id: String::from(scan_id),
+        path: path.to_path_buf(),
+        running: true,
+        dry_run: settings.dry_run,
+        ..Default::default()
+    };
+
+    let (path, scan_id) = (path.to_path_buf(), String::from(scan_id));
+    thread::spawn(move || {
+        let result = match compression {
+            Some(compression) => compress_file(&path, compression, &scan_id, &settings),
+            None => archive_dir(&path, &scan_id, &settings),
+        };
+
+        let mut r = run.write();
+        r.result = Some(result);
+        r.running = false;
+    });
+
+    Ok(())
+}
+
+/// Deletes `items` on a background thread, reporting into `run`. Each item
+/// comes with its usage in the scan's mode. Refused while `run` is still going.
+fn start_deletions(
+    items: Vec<(PlanItem, u64)>,
+    scan_id: &str,
+    allow_protected: bool,
+    settings: ActionSettings,
+    run: Arc<RwLock<DeletionRun>>,
+) -> Result<(), String> {
+    if run.read().running {
+        return Err(String::from("Still deleting, try again when it's done"));
     }
+
+    *run.write() = DeletionRun {
+        scan_id: String::from(scan_id),
+        usage: items.iter().map(|(_, usage)| *usage).collect(),
+        running: true,
+        dry_run: settings.dry_run,
+        ..Default::default()
+    };
+
+    let scan_id = String::from(scan_id);
+    thread::spawn(move || {
+        for (item, _) in items {
+            let result = delete_with_progress(&item.path, item.bytes, item.mode, &scan_id, allow_protected, &settings, &mut |progress| {
+                run.write().progress = Some(progress.clone());
+            });
+
+            let mut r = run.write();
+            r.progress = None;
+            r.results.push(PlanResult { item, result });
+        }
+
+        run.write().running = false;
+    });
+
+    Ok(())
+}
+
+/// The outermost of `dirs`, so nothing is deleted twice
+fn outermost(dirs: &[(PathBuf, u64)]) -> Vec<(PathBuf, u64)> {
+    dirs.iter()
+        .filter(|(dir, _)| !dirs.iter().any(|(other, _)| other != dir && dir.starts_with(other)))
+        .cloned()
+        .collect()
 }
 
 fn render_results(ui: &mut Ui, ctx: &egui::Context, state: &Scan, ui_state: &RefCell<UiState>) {
@@ -248,37 +788,59 @@ fn render_results(ui: &mut Ui, ctx: &egui::Context, state: &Scan, ui_state: &Ref
             //let mut s = ui_state.borrow_mut();
             s.current_tab = CurrentTab::LargeFiles;
         }
+        if ui.selectable_value(&mut s.current_tab, CurrentTab::LargeDirs, "Large directories").clicked() {
+            s.current_tab = CurrentTab::LargeDirs;
+        }
         if ui.selectable_value(&mut s.current_tab, CurrentTab::Recs, "Recommendations").clicked() {
             //let mut s = ui_state.borrow_mut();
             s.current_tab = CurrentTab::Recs;
         };
+        let errors_label = format!("Errors ({})", state.errors.len());
+        if ui.selectable_value(&mut s.current_tab, CurrentTab::Errors, errors_label).clicked() {
+            s.current_tab = CurrentTab::Errors;
+        };
     });
 
     let mut show_confirm = s.show_delete_confirm.clone();
+    let mut show_developer_delete_confirm = s.show_developer_delete_confirm.clone();
+    let junk_to_delete = s.junk_to_delete;
+    let protected_file = s.file_to_delete.as_ref().map(|f| f.0.clone()).filter(|path| s.is_protected(path));
+    let mut protected_confirm_text = s.protected_confirm_text.clone();
 
-    confirm(
-        ui,
-        ctx,
-        "Are you sure you want to delete that file?",
-        &mut show_confirm,
-        |confirm| {
-            println!("Closing window here");
-            let mut s = ui_state.borrow_mut();
+    // Drop our mutable reference to ui_state, the dialogs borrow it when closed
+    drop(s);
 
-            if confirm {
-                if let Some(file_to_delete) = s.file_to_delete.clone() {
-                    println!("Deleting file {} {}", file_to_delete.0, file_to_delete.1);
-                    delete_file(file_to_delete.0, file_to_delete.1);
-                }
+    let close = |confirm: bool| {
+        println!("Closing window here");
+        let mut s = ui_state.borrow_mut();
+
+        if confirm {
+            if let Some((path, len, mode)) = s.file_to_delete.clone() {
+                println!("Deleting file {} {:?}", path.display(), mode);
+                let item = PlanItem { path, bytes: state.mode.bytes(len), mode };
+                s.run_error =
+                    start_deletions(vec![(item, len)], &state.id, protected_file.is_some(), s.action_settings(), s.deletion_run.clone())
+                        .err();
             }
+        }
 
-            s.file_to_delete = None;
+        s.file_to_delete = None;
 
-            s.show_delete_confirm = false;
-        },
-    );
+        s.show_delete_confirm = false;
+    };
 
-    let mut show_developer_delete_confirm = s.show_developer_delete_confirm.clone();
+    match &protected_file {
+        Some(path) => {
+            confirm_protected(ctx, path, &mut protected_confirm_text, &mut show_confirm, close);
+
+            let mut s = ui_state.borrow_mut();
+            s.protected_confirm_text = match s.show_delete_confirm {
+                true => protected_confirm_text,
+                false => String::new(),
+            };
+        }
+        None => confirm(ui, ctx, "Are you sure you want to delete that file?", &mut show_confirm, close),
+    }
 
     confirm(
         ui,
@@ -290,95 +852,345 @@ fn render_results(ui: &mut Ui, ctx: &egui::Context, state: &Scan, ui_state: &Ref
             let mut s = ui_state.borrow_mut();
 
             if confirm {
+                let items = outermost(&state.developer_dirs)
+                    .into_iter()
+                    .map(|(path, len)| (PlanItem { path, bytes: state.mode.bytes(len), mode: DeleteMode::Force }, len))
+                    .collect();
+                s.run_error = start_deletions(items, &state.id, false, s.action_settings(), s.deletion_run.clone()).err();
             }
 
             s.show_developer_delete_confirm = false;
         },
     );
 
-    // Drop our mutable reference to ui_state
-    drop(s);
+    if let Some(kind) = junk_to_delete {
+        let mut open = true;
+
+        confirm(
+            ui,
+            ctx,
+            &format!("Move all {} to the trash?", kind.to_string().to_lowercase()),
+            &mut open,
+            |confirm| {
+                let mut s = ui_state.borrow_mut();
+
+                // Dangling symlinks are removed outright, there's nothing behind them to restore
+                let mode = match kind {
+                    JunkKind::DanglingSymlink => DeleteMode::Force,
+                    _ => DeleteMode::Trash,
+                };
+
+                if confirm {
+                    let items = state
+                        .junk
+                        .iter()
+                        .filter(|junk| junk.kind == kind)
+                        .map(|junk| (PlanItem { path: junk.path.clone(), bytes: junk.len, mode }, state.mode.weight(junk.len)))
+                        .collect();
+                    s.run_error = start_deletions(items, &state.id, false, s.action_settings(), s.deletion_run.clone()).err();
+                }
+
+                s.junk_to_delete = None;
+            },
+        );
 
-    let uis = ui_state.borrow();
-    match uis.current_tab {
+        if !open {
+            ui_state.borrow_mut().junk_to_delete = None;
+        }
+    }
+
+    confirm_relocate(ctx, &state.id, state.mode, ui_state);
+
+    let current_tab = ui_state.borrow().current_tab.clone();
+    match current_tab {
         CurrentTab::LargeFiles => render_large_files(ui, ctx, state, ui_state),
+        CurrentTab::LargeDirs => render_large_dirs(ui, ctx, state, ui_state),
         CurrentTab::Recs => render_recs(ui, ctx, state, ui_state),
         CurrentTab::Summary => render_summary(ui, ctx, state, ui_state),
+        CurrentTab::Errors => render_errors(ui, ctx, state),
     }
 }
 
+fn render_errors(ui: &mut Ui, _ctx: &egui::Context, scan_results: &Scan) {
+    ScrollArea::vertical().show(ui, |ui| {
+        if !scan_results.broken_symlinks.is_empty() {
+            ui.heading("Broken symlinks");
+            for symlink in scan_results.broken_symlinks.iter() {
+                ui.label(symlink.to_string());
+            }
+
+            ui.separator();
+        }
+
+        if scan_results.errors.is_empty() {
+            ui.label("No errors while scanning");
+            return;
+        }
+
+        ui.label(format!(
+            "{} paths could not be read, so the totals above may be incomplete",
+            scan_results.errors.len()
+        ));
+
+        ui.heading("Summary");
+        for summary in summarize_errors(&scan_results.errors).iter() {
+            ui.label(summary.to_string());
+        }
+
+        ui.separator();
+
+        ui.heading("All errors");
+        for error in scan_results.errors.iter() {
+            ui.label(error.to_string());
+        }
+    });
+}
+
 fn render_summary(ui: &mut Ui, ctx: &egui::Context, scan_results: &Scan, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
     ScrollArea::vertical().show(ui, |ui| {
         let r = scan_results;
 
+        let total = match r.mode {
+            UsageMode::Bytes => r.total_bytes,
+            UsageMode::Files => r.num_files,
+        };
+
         let make_bar = |v: u64, t: &str| -> ProgressBar {
-            ProgressBar::new((v as f32) / (r.total_bytes as f32)).text(t)
+            ProgressBar::new((v as f32) / (total as f32)).text(format!("{} ({})", t, format_usage(v, r.mode, units)))
         };
 
         ui.label(format!("Scanned {} files", r.num_files));
 
+        if r.tree.len() > 1 {
+            for (root, usage) in r.root_usage().iter() {
+                ui.label(format!("{}: {}", root.display(), format_usage(*usage, r.mode, units)));
+            }
+        }
+
         ui.add(make_bar(r.total_images, "Images"));
         ui.add(make_bar(r.total_videos, "Videos"));
+        ui.add(make_bar(r.total_music, "Music"));
         ui.add(make_bar(r.total_documents, "Documents"));
-        ui.add(make_bar(r.total_documents, "Other"));
+        ui.add(make_bar(r.total_archives, "Archives"));
+        ui.add(make_bar(r.total_binaries, "Binaries"));
+        ui.add(make_bar(r.total_other, "Other"));
+
+        if !r.exclusions.is_empty() {
+            ui.separator();
+            ui.heading("Excluded");
+            for (rule, count) in r.exclusions.iter() {
+                ui.label(format!("{}: {} entries", rule, count));
+            }
+        }
     });
 }
 
 
 fn render_large_files(ui: &mut Ui, _ctx: &egui::Context, state: &Scan, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
     ScrollArea::vertical().show(ui, |ui| {
         if state.largest_files.len() == 0 {
             let s = ui_state.borrow();
-            ui.label(format!("No large files detected (> {})", bytes_to_human(*s.setting_nlargest.borrow())));
-        }
-        TableBuilder::new(ui)
-            .striped(true)
-            .cell_layout(egui::Layout::left_to_right().with_cross_align(egui::Align::Center))
-            .column(Size::remainder().at_least(400.0))
-            .column(Size::initial(110.0).at_least(90.0))
-            .column(Size::initial(110.0).at_least(90.0))
-            .resizable(true)
-            .body(|mut body| {
-                for file in state.largest_files.iter() {
-                    body.row(30.0, |mut row| {
-                        row.col(|ui| {
-                            ui.label(format!("{} ({})", file.0, bytes_to_human(file.1)));
-                        });
+            ui.label(format!("No large files detected (> {})", bytes_to_human(*s.setting_largebytes.borrow(), units)));
+        }
+        if state.mode == UsageMode::Files {
+            ui.label("Directories with the most files");
+        }
+        render_delete_table(ui, &state.largest_files, false, state.mode, &state.id, ui_state);
+    });
+}
 
-                        row.col(|ui| {
-                            if ui.button("Delete (trash)").clicked() {
-                                // let mut s = ui_state.borrow_mut();
-                                let mut s = ui_state.borrow_mut();
-                                s.show_delete_confirm = true;
-                                s.file_to_delete = Some((file.0.clone(), false));
-                                // *show_delete_confirm = true;
-                            }
-                        });
+fn render_large_dirs(ui: &mut Ui, _ctx: &egui::Context, state: &Scan, ui_state: &RefCell<UiState>) {
+    ScrollArea::vertical().show(ui, |ui| {
+        let leaf_most = {
+            let mut s = ui_state.borrow_mut();
+            ui.checkbox(&mut s.show_leaf_dirs, "Only show the innermost heavy directories");
+            s.show_leaf_dirs
+        };
+
+        let dirs = state.large_dirs(leaf_most);
+        if dirs.is_empty() {
+            ui.label("No directories scanned");
+        }
+        render_delete_table(ui, &dirs, true, state.mode, &state.id, ui_state);
+    });
+}
+
+/// A checkbox adding `path` to the cleanup plan. Plans need byte counts, so it's
+/// disabled when ranking by file count.
+fn plan_checkbox(ui: &mut Ui, path: &Path, len: u64, mode: UsageMode, scan_id: &str, ui_state: &RefCell<UiState>) {
+    let mut s = ui_state.borrow_mut();
+    let mut ticked = s.plan.contains(path);
+
+    let checkbox = ui
+        .add_enabled(mode == UsageMode::Bytes, Checkbox::new(&mut ticked, ""))
+        .on_hover_text("Add to the cleanup plan")
+        .on_disabled_hover_text("Rank by bytes to add to the cleanup plan");
+
+    if checkbox.changed() {
+        if s.plan.is_empty() {
+            s.plan.scan_id = String::from(scan_id);
+        }
+        match ticked {
+            true => s.plan.add(path, len, DeleteMode::Trash),
+            false => s.plan.remove(path),
+        }
+    }
+}
+
+/// A table of paths with their usage and buttons to delete or compress each
+/// one. `dirs` says whether the paths are directories, which are archived
+/// rather than compressed.
+fn render_delete_table(
+    ui: &mut Ui,
+    entries: &[(PathBuf, u64)],
+    dirs: bool,
+    mode: UsageMode,
+    scan_id: &str,
+    ui_state: &RefCell<UiState>,
+) {
+    let units = ui_state.borrow().units();
+    TableBuilder::new(ui)
+        .striped(true)
+        .cell_layout(egui::Layout::left_to_right().with_cross_align(egui::Align::Center))
+        .column(Size::initial(24.0))
+        .column(Size::remainder().at_least(400.0))
+        .column(Size::initial(110.0).at_least(90.0))
+        .column(Size::initial(110.0).at_least(90.0))
+        .column(Size::initial(90.0).at_least(80.0))
+        .column(Size::initial(90.0).at_least(80.0))
+        .resizable(true)
+        .body(|mut body| {
+            for file in entries.iter() {
+                body.row(30.0, |mut row| {
+                    row.col(|ui| {
+                        plan_checkbox(ui, &file.0, file.1, mode, scan_id, ui_state);
+                    });
+
+                    row.col(|ui| {
+                        ui.label(format!("{} ({})", file.0.display(), format_usage(file.1, mode, units)));
+                        if ui_state.borrow().is_protected(&file.0) {
+                            ui.colored_label(egui::Color32::RED, "protected")
+                                .on_hover_text("Deleting it needs its full path typed out");
+                        }
+                    });
 
-                        row.col(|ui| {
-                            if ui.button("Delete (force)").clicked() {
-                                // let mut s = ui_state.borrow_mut();
-                                let mut s = ui_state.borrow_mut();
-                                s.show_delete_confirm = true;
-                                s.file_to_delete = Some((file.0.clone(), true));
-                                // *show_delete_confirm = true;
+                    row.col(|ui| {
+                        if ui.button("Delete (trash)").clicked() {
+                            let mut s = ui_state.borrow_mut();
+                            s.show_delete_confirm = true;
+                            s.file_to_delete = Some((file.0.clone(), file.1, DeleteMode::Trash));
+                        }
+                    });
+
+                    row.col(|ui| {
+                        if ui.button("Delete (force)").clicked() {
+                            let mut s = ui_state.borrow_mut();
+                            s.show_delete_confirm = true;
+                            s.file_to_delete = Some((file.0.clone(), file.1, DeleteMode::Force));
+                        }
+                    });
+
+                    row.col(|ui| {
+                        ui.add_enabled_ui(mode == UsageMode::Bytes, |ui| {
+                            let (run, settings) = {
+                                let s = ui_state.borrow();
+                                (s.shrink_run.clone(), s.action_settings())
+                            };
+                            ui.menu_button("Compress", |ui| {
+                                if dirs {
+                                    if ui.button("Archive to .tar.zst").clicked() {
+                                        ui_state.borrow_mut().run_error = start_shrink(&file.0, None, scan_id, settings.clone(), run.clone()).err();
+                                        ui.close_menu();
+                                    }
+                                    return;
+                                }
+                                for compression in [Compression::Gzip, Compression::Zstd] {
+                                    if ui.button(format!("Compress with {}", compression)).clicked() {
+                                        ui_state.borrow_mut().run_error =
+                                            start_shrink(&file.0, Some(compression), scan_id, settings.clone(), run.clone()).err();
+                                        ui.close_menu();
+                                    }
+                                }
+                            })
+                            .response
+                            .on_hover_text("Replace with a compressed copy once it's checked");
+                        })
+                        .response
+                        .on_disabled_hover_text("Rank by bytes to compress");
+                    });
+
+                    row.col(|ui| {
+                        if ui.button("Relocate…").on_hover_text("Move to another directory or disk").clicked() {
+                            if let Some(destination) = FileDialog::new().pick_folder() {
+                                ui_state.borrow_mut().relocate_request = Some(RelocateRequest {
+                                    path: file.0.clone(),
+                                    usage: file.1,
+                                    free: free_space(&destination),
+                                    destination,
+                                    leave_symlink: false,
+                                });
                             }
-                        });
+                        }
                     });
-                }
-            });
-    });
+                });
+            }
+        });
 }
 
 fn render_recs(ui: &mut Ui, ctx: &egui::Context, scan_results: &Scan, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
     ScrollArea::vertical().show(ui, |ui| {
         ui.heading("Developer Directories");
         ui.label("These directories contain locally-synced installation files created while developing software. In most cases they can be safely deleted as they will be re-created when needed.");
-        ui.label(format!("Detected {}", bytes_to_human(scan_results.dev_total_usage)));
+        ui.label(format!("Detected {}", format_usage(scan_results.dev_total_usage, scan_results.mode, units)));
         if ui.button("Delete all").clicked() {
             let mut s = ui_state.borrow_mut();
             s.show_developer_delete_confirm = true;
         }
+
+        CollapsingHeader::new(format!("Show {} developer directories", scan_results.developer_dirs.len()))
+            .show(ui, |ui| {
+                for dir in scan_results.developer_dirs.iter() {
+                    ui.horizontal(|ui| {
+                        plan_checkbox(ui, &dir.0, dir.1, scan_results.mode, &scan_results.id, ui_state);
+                        ui.label(format!("{} ({})", dir.0.display(), format_usage(dir.1, scan_results.mode, units)));
+                    });
+                }
+            });
+
+        ui.separator();
+
+        ui.heading("Junk");
+        ui.label("Leftovers that are almost always safe to remove: dangling symlinks, empty directories, zero-byte files and temporary or backup files.");
+
+        let summary = summarize_junk(&scan_results.junk);
+        if summary.is_empty() {
+            ui.label("No junk found");
+        }
+
+        for (kind, count, len) in summary {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: {} ({})", kind, count, bytes_to_human(len, units)));
+                if ui.button("Move all to trash").clicked() {
+                    ui_state.borrow_mut().junk_to_delete = Some(kind);
+                }
+            });
+
+            CollapsingHeader::new(format!("Show {}", kind.to_string().to_lowercase()))
+                .id_source(kind)
+                .show(ui, |ui| {
+                    for entry in scan_results.junk.iter().filter(|j| j.kind == kind) {
+                        ui.horizontal(|ui| {
+                            ui.label(entry.path.display().to_string());
+                            if ui_state.borrow().is_protected(&entry.path) {
+                                ui.colored_label(egui::Color32::RED, "protected, won't be removed");
+                            }
+                        });
+                    }
+                });
+        }
     });
 }
 
@@ -397,17 +1209,295 @@ fn render_settings(ui: &mut Ui, ctx: &egui::Context, scan_results: &Scan, ui_sta
                 .striped(true)
                 .show(ui, |ui| {
                     let mut setting_developer_cache_dirs = state.setting_developer_cache_dirs.borrow_mut();
-                    let mut setting_ignore_glob = state.setting_ignore_glob.borrow_mut();
 
-                    ui.label("Developer cache dirs glob");
-                    ui.text_edit_singleline(&mut *setting_developer_cache_dirs);
+                    ui.label("Developer cache dirs globs (one per line)");
+                    ui.text_edit_multiline(&mut *setting_developer_cache_dirs);
                     ui.end_row();
 
-                    ui.label("Ignore dirs");
-                    ui.text_edit_singleline(&mut *setting_ignore_glob);
+                    let mut setting_theme = state.setting_theme.borrow_mut();
+
+                    ui.label("Theme");
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut *setting_theme, Theme::Dark, "Dark");
+                        ui.selectable_value(&mut *setting_theme, Theme::Light, "Light");
+                    });
                     ui.end_row();
+
+                    {
+                        let mut setting_hidden = state.setting_hidden.borrow_mut();
+                        let mut setting_follow_symlinks = state.setting_follow_symlinks.borrow_mut();
+                        let mut setting_one_filesystem = state.setting_one_filesystem.borrow_mut();
+                        let mut setting_nlargest = state.setting_nlargest.borrow_mut();
+                        let mut setting_threads = state.setting_threads.borrow_mut();
+                        let mut setting_mode = state.setting_mode.borrow_mut();
+
+                        ui.label("Size mode");
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut *setting_mode, UsageMode::Bytes, "Bytes");
+                            ui.selectable_value(&mut *setting_mode, UsageMode::Files, "File count");
+                        });
+                        ui.end_row();
+
+                        ui.label("Include hidden files");
+                        ui.checkbox(&mut *setting_hidden, "");
+                        ui.end_row();
+
+                        ui.label("Follow symlinks");
+                        ui.checkbox(&mut *setting_follow_symlinks, "");
+                        ui.end_row();
+
+                        ui.label("Stay on one filesystem");
+                        ui.checkbox(&mut *setting_one_filesystem, "");
+                        ui.end_row();
+
+                        ui.label("Number of largest files to list");
+                        ui.add(DragValue::new(&mut *setting_nlargest).clamp_range(1..=10_000));
+                        ui.end_row();
+
+                        ui.label("Scanning threads");
+                        ui.add(DragValue::new(&mut *setting_threads).clamp_range(1..=64));
+                        ui.end_row();
+                    }
+
+                    let mut changed = false;
+                    {
+                        let mut setting_units = state.setting_units.borrow_mut();
+                        let mut setting_largebytes_text = state.setting_largebytes_text.borrow_mut();
+
+                        ui.label("Size units");
+                        let units_changed = ui.horizontal(|ui| {
+                            ui.selectable_value(&mut *setting_units, SizeUnits::Iec, "KiB, MiB (1024)").changed()
+                                | ui.selectable_value(&mut *setting_units, SizeUnits::Si, "kB, MB (1000)").changed()
+                        }).inner;
+                        ui.end_row();
+
+                        if units_changed {
+                            if let Ok(largebytes) = parse_size(&setting_largebytes_text) {
+                                *setting_largebytes_text = size_text(largebytes, *setting_units);
+                            }
+                        }
+
+                        ui.label("Large file size (e.g. 500M, 2GiB, 1.5 TB)");
+                        changed |= ui.text_edit_singleline(&mut *setting_largebytes_text).changed();
+                        ui.end_row();
+                    }
+                    {
+                        let mut setting_ignore_glob = state.setting_ignore_glob.borrow_mut();
+                        let mut setting_exclude_files = state.setting_exclude_files.borrow_mut();
+                        let mut setting_use_gitignore = state.setting_use_gitignore.borrow_mut();
+
+                        ui.label("Ignore patterns (one per line, !pattern to include)");
+                        changed |= ui.text_edit_multiline(&mut *setting_ignore_glob).changed();
+                        ui.end_row();
+
+                        ui.label("Exclude files (gitignore syntax)");
+                        changed |= ui.text_edit_multiline(&mut *setting_exclude_files).changed();
+                        ui.end_row();
+
+                        ui.label("Honor .gitignore files");
+                        changed |= ui.checkbox(&mut *setting_use_gitignore, "").changed();
+                        ui.end_row();
+                    }
+
+                    if changed {
+                        state.validate_settings();
+                    }
+
+                    let protected_changed = {
+                        let mut setting_protected_paths = state.setting_protected_paths.borrow_mut();
+
+                        ui.label("Protected paths (one per line)");
+                        let changed = ui.text_edit_multiline(&mut *setting_protected_paths).changed();
+                        ui.end_row();
+
+                        changed
+                    };
+
+                    if protected_changed {
+                        *state.protected.borrow_mut() = ProtectedPaths::new(&state.protected_paths());
+                    }
+                });
+
+            if let Some(error) = &*state.settings_error.borrow() {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+}
+
+fn render_history(_ui: &mut Ui, ctx: &egui::Context, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
+    let state = ui_state.borrow();
+    let mut open = state.show_history.borrow_mut();
+
+    Window::new("History")
+        .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .open(&mut open)
+        .show(ctx, |ui| {
+            if ui.button("Refresh").clicked() {
+                state.load_history();
+            }
+
+            let history = state.history.borrow();
+            if let Some(error) = &*state.history_error.borrow() {
+                ui.colored_label(egui::Color32::RED, error);
+            } else if history.is_empty() {
+                ui.label("Nothing has been deleted yet");
+            }
+
+            ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                egui::Grid::new("history_grid")
+                    .num_columns(5)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for entry in history.iter() {
+                            ui.label(entry.timestamp.format("%Y-%m-%d %T").to_string());
+                            ui.label(&entry.user);
+                            ui.label(entry.action.to_string());
+                            ui.label(format!("{} ({})", entry.path.display(), bytes_to_human(entry.len, units)));
+                            match &entry.error {
+                                Some(error) => ui.colored_label(egui::Color32::RED, error),
+                                None if entry.dry_run => ui.label("Dry run"),
+                                None => ui.label("Done"),
+                            };
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+}
+
+fn render_trash(ui: &mut Ui, ctx: &egui::Context, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
+    let state = ui_state.borrow();
+    let mut open = *state.show_trash.borrow();
+    let mut restoring = None;
+
+    Window::new("Trash")
+        .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .open(&mut open)
+        .show(ctx, |ui| {
+            if ui.button("Refresh").clicked() {
+                *state.trash_error.borrow_mut() = None;
+                state.load_trash();
+            }
+
+            if let Some(error) = &*state.trash_error.borrow() {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            let items = state.trash_items.borrow();
+            if items.is_empty() {
+                ui.label("Nothing this app moved to the trash is left in it");
+            }
+
+            ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                egui::Grid::new("trash_grid")
+                    .num_columns(4)
+                    .spacing([20.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for item in items.iter() {
+                            ui.label(item.deleted_at.format("%Y-%m-%d %T").to_string());
+                            ui.label(format!("{} ({})", item.path.display(), bytes_to_human(item.len, units)));
+                            if ui.button("Restore").clicked() {
+                                restoring = Some(item.clone());
+                            }
+                            if ui.button("Purge").clicked() {
+                                *state.item_to_purge.borrow_mut() = Some(item.clone());
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+
+    *state.show_trash.borrow_mut() = open;
+
+    if let Some(item) = restoring {
+        *state.trash_error.borrow_mut() = restore::restore(item, "", state.dry_run).err();
+        state.load_trash();
+    }
+
+    let purging = state.item_to_purge.borrow().clone();
+    if let Some(item) = purging {
+        let mut open = true;
+
+        confirm(
+            ui,
+            ctx,
+            &format!("Remove {} from the trash for good?", item.path.display()),
+            &mut open,
+            |confirm| {
+                if confirm {
+                    *state.trash_error.borrow_mut() = restore::purge(item.clone(), "", state.dry_run).err();
+                    state.load_trash();
+                }
+
+                *state.item_to_purge.borrow_mut() = None;
+            },
+        );
+
+        if !open {
+            *state.item_to_purge.borrow_mut() = None;
+        }
+    }
+}
+
+/// Asks whether to go ahead with the move picked in the results, showing how
+/// much room there is for it
+fn confirm_relocate(ctx: &egui::Context, scan_id: &str, mode: UsageMode, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
+    let mut request = match ui_state.borrow().relocate_request.clone() {
+        Some(request) => request,
+        None => return,
+    };
+    let mut open = true;
+    let mut close = None;
+
+    Window::new("Move to another place?")
+        .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Move {} ({}) into {}",
+                request.path.display(),
+                format_usage(request.usage, mode, units),
+                request.destination.display()
+            ));
+            match request.free {
+                Some(free) if mode == UsageMode::Bytes && free < request.usage => ui.colored_label(
+                    egui::Color32::RED,
+                    format!("Only {} free there, it won't fit", bytes_to_human(free, units)),
+                ),
+                Some(free) => ui.label(format!("{} free there", bytes_to_human(free, units))),
+                None => ui.label("Free space there is unknown"),
+            };
+            ui.checkbox(&mut request.leave_symlink, "Leave a symlink behind");
+
+            ui.horizontal(|ui| {
+                ui.with_layout(Layout::right_to_left(), |ui| {
+                    if ui.button("Move").clicked() {
+                        close = Some(true);
+                    } else if ui.button("Cancel").clicked() {
+                        close = Some(false);
+                    }
                 });
+            })
         });
+
+    let mut s = ui_state.borrow_mut();
+    match close {
+        Some(true) => {
+            s.run_error = start_relocate(request, scan_id, s.action_settings(), s.relocate_run.clone()).err();
+            s.relocate_request = None;
+        }
+        Some(false) => s.relocate_request = None,
+        None if !open => s.relocate_request = None,
+        None => s.relocate_request = Some(request),
+    }
 }
 
 fn confirm<F>(ui: &mut Ui, ctx: &egui::Context, title: &str, open: &mut bool, close: F)
@@ -436,47 +1526,193 @@ where
         });
 }
 
+fn render_plan(ui: &mut Ui, ctx: &egui::Context, ui_state: &RefCell<UiState>) {
+    let units = ui_state.borrow().units();
+    let mut open = *ui_state.borrow().show_plan.borrow();
+    let run = ui_state.borrow().plan_run.clone();
+
+    Window::new("Cleanup plan")
+        .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .open(&mut open)
+        .show(ctx, |ui| {
+            let r = run.read();
+
+            if r.running || !r.results.is_empty() {
+                ui.add(ProgressBar::new(r.results.len() as f32 / r.usage.len().max(1) as f32).text(format!(
+                    "{} of {} items",
+                    r.results.len(),
+                    r.usage.len()
+                )));
+                if r.running {
+                    ui.label(r.progress_text(units));
+                }
+
+                ScrollArea::vertical().id_source("plan_results").max_height(200.0).show(ui, |ui| {
+                    egui::Grid::new("plan_results_grid").num_columns(2).striped(true).show(ui, |ui| {
+                        for result in r.results.iter() {
+                            ui.label(result.item.path.display().to_string());
+                            match &result.result {
+                                Ok(_) if r.dry_run => ui.label("Would delete"),
+                                Ok(_) => ui.label("Deleted"),
+                                Err(e) => ui.colored_label(egui::Color32::RED, e),
+                            };
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                if r.running {
+                    ctx.request_repaint();
+                } else if r.dry_run {
+                    ui.label(format!("Would reclaim {}", bytes_to_human(reclaimed_bytes(&r.results), units)));
+                } else {
+                    ui.label(format!("Reclaimed {}", bytes_to_human(reclaimed_bytes(&r.results), units)));
+                }
+
+                ui.separator();
+            }
+
+            let running = r.running;
+            drop(r);
+
+            let mut s = ui_state.borrow_mut();
+            let protected = s.protected.borrow().clone();
+            let mut remove = None;
+
+            ScrollArea::vertical().id_source("plan_items").max_height(300.0).show(ui, |ui| {
+                egui::Grid::new("plan_grid").num_columns(3).striped(true).show(ui, |ui| {
+                    for item in s.plan.items.iter_mut() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{} ({})", item.path.display(), bytes_to_human(item.bytes, units)));
+                            if protected.is_protected(&item.path) {
+                                ui.colored_label(egui::Color32::RED, "protected, will be refused");
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut item.mode, DeleteMode::Trash, "Trash");
+                            ui.selectable_value(&mut item.mode, DeleteMode::Force, "Force");
+                        });
+                        if ui.button("Remove").clicked() {
+                            remove = Some(item.path.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+
+            if let Some(path) = remove {
+                s.plan.remove(&path);
+            }
+
+            ui.label(format!("{} items, {} to reclaim", s.plan.items.len(), bytes_to_human(s.plan.total_bytes(), units)));
+
+            ui.horizontal(|ui| {
+                if ui.button("Export...").clicked() {
+                    if let Some(file) = FileDialog::new().add_filter("Cleanup plan", &["toml"]).save_file() {
+                        s.plan_error = s.plan.save(&file).err().map(|e| e.to_string());
+                    }
+                }
+                if ui.add_enabled(!running, Button::new("Import...")).clicked() {
+                    if let Some(file) = FileDialog::new().add_filter("Cleanup plan", &["toml"]).pick_file() {
+                        match CleanupPlan::load(&file) {
+                            Ok(plan) => {
+                                s.plan = plan;
+                                s.plan_error = None;
+                            }
+                            Err(e) => s.plan_error = Some(e.to_string()),
+                        }
+                    }
+                }
+                if ui.add_enabled(!running, Button::new("Clear")).clicked() {
+                    s.plan.items.clear();
+                }
+                if ui.add_enabled(!running && !s.plan.is_empty(), Button::new("Run")).clicked() {
+                    s.show_plan_confirm = true;
+                }
+            });
+
+            if let Some(error) = &s.plan_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+
+    *ui_state.borrow().show_plan.borrow_mut() = open;
+
+    let (mut show_confirm, title) = {
+        let s = ui_state.borrow();
+        let title = format!("Delete {} items ({})?", s.plan.items.len(), bytes_to_human(s.plan.total_bytes(), units));
+        (s.show_plan_confirm, title)
+    };
+
+    if show_confirm {
+        confirm(ui, ctx, &title, &mut show_confirm, |confirm| {
+            let mut s = ui_state.borrow_mut();
+            if confirm {
+                // Plans are only built when ranking by bytes, so usage is bytes
+                let items = s.plan.outermost().into_iter().map(|item| (item.clone(), item.bytes)).collect();
+                s.plan_error = start_deletions(items, &s.plan.scan_id, false, s.action_settings(), s.plan_run.clone()).err();
+            }
+            s.show_plan_confirm = false;
+        });
+
+        if !show_confirm {
+            ui_state.borrow_mut().show_plan_confirm = false;
+        }
+    }
+}
+
+/// Like `confirm`, but Confirm only works once `path` has been typed out in full
+fn confirm_protected<F>(ctx: &egui::Context, path: &Path, typed: &mut String, open: &mut bool, close: F)
+where
+    F: FnOnce(bool),
+{
+    Window::new("Delete a protected path?")
+        .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+        .open(open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.colored_label(
+                egui::Color32::RED,
+                format!("{} is protected. Type its full path to delete it anyway.", path.display()),
+            );
+            ui.text_edit_singleline(typed);
+
+            let matches = *typed == path.to_string_lossy();
+            ui.horizontal(|ui| {
+                ui.with_layout(Layout::right_to_left(), |ui| {
+                    if ui.add_enabled(matches, Button::new("Confirm")).clicked() {
+                        close(true);
+                    } else if ui.button("Cancel").clicked() {
+                        close(false);
+                    }
+                });
+            })
+        });
+}
+
 impl App {
-    pub fn new() -> Self {
-        // let home = home_dir();
+    pub fn new(config_path: Option<PathBuf>) -> Self {
+        let (config, config_error) = match Config::load(config_path.as_deref()) {
+            Ok(config) => (config, None),
+            Err(e) => (Config::default(), Some(format!("{}, using defaults", e))),
+        };
+
+
+        let window_size = Vec2::new(config.window_size[0], config.window_size[1]);
 
         let mut options = eframe::NativeOptions::default();
-        options.initial_window_size = Some(Vec2::new(1024.0, 768.0));
-        let ui_state = RefCell::new(UiState {
-            show_delete_confirm: false,
-            show_developer_delete_confirm: false,
-            show_settings: RefCell::new(false),
-            file_to_delete: None,
-            current_tab: CurrentTab::Summary,
-            setting_developer_cache_dirs: RefCell::new(String::from("**/node_modules")),
-            setting_ignore_glob: RefCell::new(String::from("")),
-            setting_hidden: RefCell::new(true),
-            setting_largebytes: RefCell::new(1024 * 1024 * 50),
-            setting_nlargest: RefCell::new(100),
-            setting_root_dir: RefCell::new(String::from("/Users/max/hack/usage-test"))
-        });
+        options.initial_window_size = Some(window_size);
+        let ui_state = RefCell::new(UiState::from_config(&config));
+        *ui_state.borrow().settings_error.borrow_mut() = config_error;
 
-        let scan_results = Arc::new(RwLock::new(Scan {
-            dir: String::from(""),
-            started_at: Local::now(),
-            completed_at: None,
-            current_file: None,
-            num_files: 0,
-            total_bytes: 0,
-            largest_files: Box::new(vec![]),
-            total_music: 0,
-            total_images: 0,
-            total_videos: 0,
-            total_documents: 0,
-            total_binaries: 0,
-            total_archives: 0,
-            total_other: 0,
-            dev_total_usage: 0,
-            developer_dirs: vec![],
-        }));
+        let scan_results = Arc::new(RwLock::new(Scan::new()));
 
         let app = App {
+            config_path,
+            window_size: RefCell::new(window_size),
             scan_results,
+            progress_events: RefCell::new(None),
             ui_state,
             scanning: Arc::new(RwLock::new(false))
         };
@@ -484,7 +1720,115 @@ impl App {
         eframe::run_native("Disk Usage", options, Box::new(|_cc| Box::new(app)));
     }
 
+    /// Takes deletions that finished since the last frame out of the scan and the plan
+    fn apply_deletions(&self) {
+        let runs = {
+            let s = self.ui_state.borrow();
+            [(s.plan_run.clone(), true), (s.deletion_run.clone(), false)]
+        };
+
+        for (run, from_plan) in runs {
+            let mut r = run.write();
+            if r.running || r.applied {
+                continue;
+            }
+            r.applied = true;
+            if r.dry_run {
+                continue;
+            }
+
+            let mut s = self.ui_state.borrow_mut();
+            let mut scan = self.scan_results.write();
+            let same_scan = r.scan_id == scan.id;
+            let mut trashed = vec![];
+
+            for (result, usage) in r.results.iter().zip(r.usage.iter()) {
+                if result.result.is_err() {
+                    continue;
+                }
+
+                if from_plan {
+                    // Items inside it went with it
+                    s.plan.items.retain(|item| !item.path.starts_with(&result.item.path));
+                }
+                if result.item.mode == DeleteMode::Trash {
+                    trashed.push(result.item.path.clone());
+                }
+                if same_scan {
+                    scan.remove_path(&result.item.path, *usage);
+                }
+            }
+
+            if !trashed.is_empty() {
+                s.last_trashed = trashed;
+            }
+        }
+    }
+
+    /// Takes a finished compression out of the scan. Only the space it saved
+    /// goes, since the compressed copy or archive is still there.
+    fn apply_shrink(&self) {
+        let run = self.ui_state.borrow().shrink_run.clone();
+        let mut r = run.write();
+        if r.running || r.applied {
+            return;
+        }
+        r.applied = true;
+        if r.dry_run {
+            return;
+        }
+
+        let mut scan = self.scan_results.write();
+        if let Some(Ok(shrunk)) = &r.result {
+            if r.scan_id == scan.id && scan.mode == UsageMode::Bytes {
+                scan.remove_path(&shrunk.path, shrunk.saved());
+                // The compressed copy or archive takes its place
+                scan.num_files += 1;
+            }
+        }
+    }
+
+    /// Takes a finished move out of the scan
+    fn apply_relocate(&self) {
+        let run = self.ui_state.borrow().relocate_run.clone();
+        let mut r = run.write();
+        if r.running || r.applied {
+            return;
+        }
+        r.applied = true;
+        if r.dry_run {
+            return;
+        }
+
+        let mut scan = self.scan_results.write();
+        if let Some(Ok(relocated)) = &r.result {
+            if r.scan_id == scan.id {
+                scan.remove_path(&relocated.path, r.usage);
+            }
+        }
+    }
+
     fn start_scan(&self) {
+        {
+            let state = self.ui_state.borrow();
+            if !state.validate_settings() {
+                return;
+            }
+            if state.roots().is_empty() {
+                *state.settings_error.borrow_mut() = Some(String::from("Add at least one directory to scan"));
+                return;
+            }
+        }
+
+        let state = self.ui_state.borrow().clone();
+        let settings = state.to_config(Vec2::ZERO).scan_settings();
+
+        // Settings the analyzer rejects are reported before anything is cleared
+        if let Err(e) = Analyzer::new(settings.clone()) {
+            *self.ui_state.borrow().settings_error.borrow_mut() = Some(format!("Unable to start scan: {}", e));
+            return;
+        }
+
         // self.scanning = RefCell::new(true);
         let mut is_scanning = self.scanning.write();
         *is_scanning = true;
@@ -493,7 +1837,8 @@ impl App {
 
         let producer_lock = self.scan_results.clone();
 
-        let state = self.ui_state.borrow().clone();
+        let (progress_tx, progress_rx) = channel();
+        *self.progress_events.borrow_mut() = Some(progress_rx);
 
         let scanning_arc = self.scanning.clone();
 
@@ -503,20 +1848,17 @@ impl App {
 
             let mut w = producer_lock.write();
             w.clear();
-            w.dir = (*state.setting_root_dir.borrow()).clone();
+            w.dirs = state.roots();
+            w.mode = *state.setting_mode.borrow();
             drop(w);
 
-            let settings = ScanSettings {
-                ignore: (*state.setting_ignore_glob.borrow()).clone(),
-                dir: (*state.setting_root_dir.borrow()).clone(),
-                nlargest: *state.setting_nlargest.borrow(),
-                largebytes: *state.setting_largebytes.borrow(),
-                hidden: *state.setting_hidden.borrow(),
-            };
-
-            let analyzer = Analyzer::new(&settings, producer_lock);
+            // Only fails if an exclude file changed since the settings were checked
+            if let Ok(analyzer) = Analyzer::new(settings) {
+                analyzer.add_progress_listener(progress_tx);
+                let results = analyzer.analyze();
 
-            analyzer.analyze().expect("Unable to read file or directory");
+                *producer_lock.write() = results;
+            }
 
             let mut is_scanning = scanning_arc.write();
             *is_scanning = false;
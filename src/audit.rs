@@ -0,0 +1,288 @@
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    utils::{bytes_to_human, escape_path, unescape_path, SizeUnits},
+};
+
+const HEADER: &str = "# disk-usage audit log v1: timestamp, user, scan, action, bytes, path, error, dry run";
+
+/// What was done to a path
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AuditAction {
+    /// Moved to the trash
+    Trash,
+    /// Removed for good
+    Force,
+    /// Restored from the trash
+    Restore,
+    /// Removed from the trash for good
+    Purge,
+    /// Replaced with a compressed copy
+    Compress,
+    /// Replaced with a .tar.zst archive
+    Archive,
+    /// Moved somewhere else
+    Relocate,
+}
+
+impl AuditAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Trash => "trash",
+            AuditAction::Force => "force",
+            AuditAction::Restore => "restore",
+            AuditAction::Purge => "purge",
+            AuditAction::Compress => "compress",
+            AuditAction::Archive => "archive",
+            AuditAction::Relocate => "relocate",
+        }
+    }
+
+    fn parse(s: &str) -> Option<AuditAction> {
+        match s {
+            "trash" => Some(AuditAction::Trash),
+            "force" => Some(AuditAction::Force),
+            "restore" => Some(AuditAction::Restore),
+            "purge" => Some(AuditAction::Purge),
+            "compress" => Some(AuditAction::Compress),
+            "archive" => Some(AuditAction::Archive),
+            "relocate" => Some(AuditAction::Relocate),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// One line of the audit log
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Local>,
+    pub user: String,
+    /// The scan the path was picked from, empty when there wasn't one
+    pub scan_id: String,
+    pub action: AuditAction,
+    pub len: u64,
+    pub path: PathBuf,
+    /// Why the action failed, `None` when it succeeded
+    pub error: Option<String>,
+    /// Whether the action was only rehearsed, leaving the filesystem untouched
+    pub dry_run: bool,
+}
+
+impl AuditEntry {
+    /// An entry for an action on `path` that just finished with `result`, or
+    /// was only rehearsed when `dry_run`
+    pub fn new(
+        path: &Path,
+        len: u64,
+        action: AuditAction,
+        scan_id: &str,
+        result: &Result<(), String>,
+        dry_run: bool,
+    ) -> AuditEntry {
+        AuditEntry {
+            timestamp: Local::now(),
+            user: current_user(),
+            scan_id: String::from(scan_id),
+            action,
+            len,
+            path: path.to_path_buf(),
+            error: result.clone().err(),
+            dry_run,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        // Tabs and newlines would break the line apart
+        let clean = |s: &str| s.replace(|c: char| c.is_control(), " ");
+
+        [
+            self.timestamp.to_rfc3339(),
+            clean(&self.user),
+            clean(&self.scan_id),
+            String::from(self.action.as_str()),
+            self.len.to_string(),
+            escape_path(&self.path),
+            self.error.as_deref().map(clean).unwrap_or_default(),
+            String::from(if self.dry_run { "dry-run" } else { "" }),
+        ]
+        .join("\t")
+    }
+
+    fn parse(line: &str) -> Option<AuditEntry> {
+        // Lines written before dry runs existed have no eighth field
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 && fields.len() != 8 {
+            return None;
+        }
+
+        Some(AuditEntry {
+            timestamp: DateTime::parse_from_rfc3339(fields[0]).ok()?.with_timezone(&Local),
+            user: String::from(fields[1]),
+            scan_id: String::from(fields[2]),
+            action: AuditAction::parse(fields[3])?,
+            len: fields[4].parse().ok()?,
+            path: unescape_path(fields[5])?,
+            error: match fields[6] {
+                "" => None,
+                error => Some(String::from(error)),
+            },
+            dry_run: fields.get(7) == Some(&"dry-run"),
+        })
+    }
+}
+
+impl AuditEntry {
+    /// One line summary with the size in `units`
+    pub fn summary(&self, units: SizeUnits) -> String {
+        let mut line = format!(
+            "{} {} {} {} ({})",
+            self.timestamp.format("%Y-%m-%d %T"),
+            self.user,
+            self.action,
+            self.path.display(),
+            bytes_to_human(self.len, units)
+        );
+
+        if let Some(error) = &self.error {
+            line.push_str(&format!(" failed: {}", error));
+        }
+        if self.dry_run {
+            line.push_str(" (dry run)");
+        }
+        if !self.scan_id.is_empty() {
+            line.push_str(&format!(" [scan {}]", self.scan_id));
+        }
+
+        line
+    }
+}
+
+/// An append-only record of every deletion, one tab separated line per entry
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new<P: Into<PathBuf>>(path: P) -> AuditLog {
+        AuditLog { path: path.into() }
+    }
+
+    /// `disk-usage/audit.log` in the user's local data directory
+    pub fn default_location() -> Option<AuditLog> {
+        dirs::data_local_dir().map(|dir| AuditLog::new(dir.join("disk-usage").join("audit.log")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `entry` and flushes it to disk before returning
+    pub fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        writeln!(file, "{}", entry.to_line())?;
+
+        file.sync_data()
+    }
+
+    /// Every entry, oldest first. Lines that can't be parsed are skipped.
+    pub fn read(&self) -> io::Result<Vec<AuditEntry>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        Ok(text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(AuditEntry::parse)
+            .collect())
+    }
+}
+
+/// Appends `entry` to the log at the default location, if there is one
+pub fn record(entry: &AuditEntry) -> Result<(), String> {
+    match AuditLog::default_location() {
+        Some(log) => log
+            .append(entry)
+            .map_err(|e| format!("unable to write the audit log {}: {}", log.path().display(), e)),
+        None => Ok(()),
+    }
+}
+
+/// The name of the user running the app, for the audit log
+fn current_user() -> String {
+    if let Some(user) = std::env::var_os("USER").or_else(|| std::env::var_os("USERNAME")) {
+        return user.to_string_lossy().to_string();
+    }
+
+    #[cfg(unix)]
+    return format!("uid {}", unsafe { libc::getuid() });
+
+    #[cfg(not(unix))]
+    return String::from("unknown");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::Path};
+
+    use super::{AuditAction, AuditEntry, AuditLog};
+
+    #[test]
+    fn entries_round_trip() {
+        let file = std::env::temp_dir().join(format!("disk-usage-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&file);
+        let log = AuditLog::new(&file);
+
+        let deleted = AuditEntry::new(Path::new("/data/old\tbackup.tar"), 4096, AuditAction::Trash, "scan-1", &Ok(()), false);
+        let failed = AuditEntry::new(
+            Path::new("/data/locked"),
+            10,
+            AuditAction::Force,
+            "",
+            &Err(String::from("Permission denied")),
+            false,
+        );
+        log.append(&deleted).unwrap();
+        log.append(&failed).unwrap();
+
+        // Written before dry runs were recorded
+        let legacy = "2022-06-01T10:00:00+00:00\tmax\t\tforce\t5\t/data/old.log\t\n";
+        std::fs::OpenOptions::new().append(true).open(&file).unwrap().write_all(legacy.as_bytes()).unwrap();
+
+        let entries = log.read().unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert!(!entries[0].dry_run);
+        assert_eq!(entries[2].path, Path::new("/data/old.log"));
+        assert!(!entries[2].dry_run);
+        assert_eq!(entries[0].path, deleted.path);
+        assert_eq!(entries[0].timestamp.timestamp(), deleted.timestamp.timestamp());
+        assert_eq!(entries[0].error, None);
+        assert_eq!(entries[1].action, AuditAction::Force);
+        assert_eq!(entries[1].error.as_deref(), Some("Permission denied"));
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::{utils::disk_space, Scan, UsageMode};
+
+const HEADER: &str = "# disk-usage snapshot: the size of each directory at its last check, for measuring growth\n\n";
+
+/// Limits checked directories and the disks holding them are compared against.
+/// Unset limits aren't checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Thresholds {
+    /// Lowest percentage of a disk that should be free
+    pub min_free_percent: Option<f64>,
+    /// Largest a directory should be, in bytes
+    pub max_size: Option<u64>,
+    /// Most a directory should grow between checks, in bytes
+    pub max_growth: Option<u64>,
+}
+
+/// What a check compares
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    /// Percentage of a disk that's free
+    DiskFree,
+    /// Bytes in a directory
+    DirSize,
+    /// Bytes a directory grew by since the last check, negative when it shrank
+    DirGrowth,
+}
+
+/// One threshold compared against one disk or directory
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub check: CheckKind,
+    /// The disk's mount point or the directory
+    #[serde(with = "crate::utils::escaped_path")]
+    pub path: PathBuf,
+    pub value: f64,
+    pub limit: f64,
+    /// Whether the value is within the limit
+    pub ok: bool,
+}
+
+/// The outcome of a check run, meant to be read by scripts
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CheckSummary {
+    /// "ok", or "alert" when any check failed
+    pub status: &'static str,
+    pub failed: usize,
+    #[serde(rename = "result")]
+    pub checks: Vec<CheckResult>,
+}
+
+impl CheckSummary {
+    pub fn new(checks: Vec<CheckResult>) -> CheckSummary {
+        let failed = checks.iter().filter(|check| !check.ok).count();
+
+        CheckSummary {
+            status: if failed == 0 { "ok" } else { "alert" },
+            failed,
+            checks,
+        }
+    }
+
+    /// The summary as TOML
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Unable to serialize check summary")
+    }
+}
+
+/// A directory's size when it was last checked
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnapshotEntry {
+    #[serde(with = "crate::utils::escaped_path")]
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// RFC 3339
+    pub taken_at: String,
+}
+
+/// The size of every directory checked so far, as of its last check
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Snapshot {
+    #[serde(rename = "dir")]
+    pub dirs: Vec<SnapshotEntry>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(path, e) => write!(f, "Unable to access snapshot {}: {}", path.display(), e),
+            SnapshotError::Parse(path, e) => write!(f, "Invalid snapshot {}: {}", path.display(), e),
+            SnapshotError::Serialize(e) => write!(f, "Unable to write snapshot: {}", e),
+        }
+    }
+}
+
+impl Snapshot {
+    /// `disk-usage/snapshot.toml` in the user's local data directory
+    pub fn default_location() -> Option<PathBuf> {
+        dirs::data_local_dir().map(|dir| dir.join("disk-usage").join("snapshot.toml"))
+    }
+
+    /// Loads the snapshot at `path`. A missing file is an empty snapshot.
+    pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Snapshot::default()),
+            Err(e) => return Err(SnapshotError::Io(path.to_path_buf(), e)),
+        };
+
+        toml::from_str(&text).map_err(|e| SnapshotError::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let text = toml::to_string_pretty(self).map_err(SnapshotError::Serialize)?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| SnapshotError::Io(dir.to_path_buf(), e))?;
+        }
+
+        fs::write(path, format!("{}{}", HEADER, text)).map_err(|e| SnapshotError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&SnapshotEntry> {
+        self.dirs.iter().find(|entry| entry.path == path)
+    }
+
+    /// Records the size of each scanned root, keeping directories the scan didn't cover
+    pub fn update(&mut self, scan: &Scan) {
+        let taken_at = Local::now().to_rfc3339();
+
+        for (path, bytes) in scan.root_usage() {
+            let entry = SnapshotEntry {
+                path,
+                bytes,
+                taken_at: taken_at.clone(),
+            };
+
+            match self.dirs.iter_mut().find(|e| e.path == entry.path) {
+                Some(existing) => *existing = entry,
+                None => self.dirs.push(entry),
+            }
+        }
+    }
+}
+
+/// Compares the roots of `scan`, and the disks holding them, against
+/// `thresholds`. Growth is measured from `previous`, and roots it doesn't have
+/// aren't checked for growth. The scan has to be in bytes.
+pub fn check(scan: &Scan, thresholds: &Thresholds, previous: &Snapshot) -> Vec<CheckResult> {
+    let mut results = vec![];
+    if scan.mode != UsageMode::Bytes {
+        return results;
+    }
+
+    if let Some(min_free) = thresholds.min_free_percent {
+        let mut disks = vec![];
+        for root in scan.dirs.iter() {
+            if let Some(disk) = disk_space(root) {
+                if !disks.contains(&disk) {
+                    disks.push(disk);
+                }
+            }
+        }
+
+        for disk in disks {
+            let free = disk.free_percent();
+            results.push(CheckResult {
+                check: CheckKind::DiskFree,
+                path: disk.mount_point,
+                value: free,
+                limit: min_free,
+                ok: free >= min_free,
+            });
+        }
+    }
+
+    for (root, bytes) in scan.root_usage() {
+        if let Some(max_size) = thresholds.max_size {
+            results.push(CheckResult {
+                check: CheckKind::DirSize,
+                path: root.clone(),
+                value: bytes as f64,
+                limit: max_size as f64,
+                ok: bytes <= max_size,
+            });
+        }
+
+        if let (Some(max_growth), Some(before)) = (thresholds.max_growth, previous.get(&root)) {
+            let growth = bytes as i128 - before.bytes as i128;
+            results.push(CheckResult {
+                check: CheckKind::DirGrowth,
+                path: root,
+                value: growth as f64,
+                limit: max_growth as f64,
+                ok: growth <= max_growth as i128,
+            });
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{check, CheckKind, CheckSummary, Snapshot, Thresholds};
+    use crate::{analyzer::FileTreeNode, Scan};
+
+    #[test]
+    fn thresholds_and_growth() {
+        let mut scan = Scan::new();
+        for (dir, len) in [("/srv/media", 900), ("/srv/logs", 300)] {
+            scan.dirs.push(PathBuf::from(dir));
+            scan.tree.push(FileTreeNode {
+                path: PathBuf::from(dir),
+                is_file: false,
+                len,
+                files: 0,
+                children: vec![],
+                empty: false,
+            });
+        }
+
+        let mut previous = Snapshot::default();
+        previous.update(&scan);
+        previous.dirs[1].bytes = 100;
+
+        let thresholds = Thresholds {
+            min_free_percent: None,
+            max_size: Some(500),
+            max_growth: Some(150),
+        };
+        let summary = CheckSummary::new(check(&scan, &thresholds, &previous));
+
+        assert_eq!(summary.checks.len(), 4);
+        assert_eq!(summary.failed, 2);
+        assert_eq!(summary.status, "alert");
+
+        let failed: Vec<_> = summary.checks.iter().filter(|c| !c.ok).map(|c| (c.check, c.path.as_path())).collect();
+        assert_eq!(failed, [(CheckKind::DirSize, "/srv/media".as_ref()), (CheckKind::DirGrowth, "/srv/logs".as_ref())]);
+
+        let toml = summary.to_toml();
+        assert!(toml.starts_with("status = \"alert\"\nfailed = 2\n"));
+        assert!(toml.contains("check = \"dir_growth\""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshot_keeps_non_utf8_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let mut scan = Scan::new();
+        let dir = PathBuf::from(OsStr::from_bytes(b"/srv/caf\xe9"));
+        scan.dirs.push(dir.clone());
+        scan.tree.push(FileTreeNode {
+            path: dir.clone(),
+            is_file: false,
+            len: 42,
+            files: 0,
+            children: vec![],
+            empty: false,
+        });
+
+        let mut snapshot = Snapshot::default();
+        snapshot.update(&scan);
+
+        let text = toml::to_string(&snapshot).unwrap();
+        assert!(text.contains("path = \"/srv/caf\\\\xe9\""));
+
+        let loaded: Snapshot = toml::from_str(&text).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.get(&dir).map(|entry| entry.bytes), Some(42));
+
+        // The summary names the directory the same way the snapshot does
+        let thresholds = Thresholds { max_size: Some(10), ..Thresholds::default() };
+        let summary = CheckSummary::new(check(&scan, &thresholds, &loaded));
+        assert!(summary.to_toml().contains("path = \"/srv/caf\\\\xe9\""));
+    }
+}
@@ -0,0 +1,910 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand};
+use colored::*;
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use sysinfo::{DiskExt, System, SystemExt};
+
+use disk_usage::{
+    audit::AuditLog,
+    check::{check, CheckSummary, Snapshot, Thresholds},
+    compress::{archive_dir, compress_file, Compression},
+    delete::{delete, delete_protected, delete_with_progress, ActionSettings, DeleteMode, RemoveProgress},
+    errors::summarize_errors,
+    junk::summarize_junk,
+    plan::{reclaimed_bytes, CleanupPlan},
+    progress::{Progress, ProgressEvent},
+    protect::ProtectedPaths,
+    relocate::{free_space, relocate},
+    restore::{self, trashed_items},
+    rules::RuleSet,
+    utils::{bytes_to_human, format_usage, inode_usage, parse_size, SizeUnits},
+    scan, Analyzer, Scan, ScanSettings, UsageMode,
+};
+
+use crate::config::Config;
+
+/// Analyze disk usage. Opens the GUI when no directories are given.
+///
+/// Settings come from the config file and can be overridden by flags.
+#[derive(Parser, Debug)]
+#[clap(name = "disk-usage", version)]
+pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Directories to scan. Overlapping directories are only counted once.
+    pub dirs: Vec<PathBuf>,
+
+    /// Config file to use instead of the one in the user's config directory
+    #[clap(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Pattern to exclude, in gitignore syntax (repeatable, `!pattern` re-includes).
+    /// Replaces the patterns from the config file.
+    #[clap(short, long)]
+    pub ignore: Vec<String>,
+
+    /// Read exclude patterns from a file in gitignore syntax (repeatable)
+    #[clap(long)]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// Honor .gitignore files found while scanning
+    #[clap(long)]
+    pub gitignore: bool,
+
+    /// Glob matching developer directories, like **/node_modules (repeatable)
+    #[clap(long)]
+    pub developer_pattern: Vec<String>,
+
+    /// Number of largest files to report
+    #[clap(short, long)]
+    pub nlargest: Option<u64>,
+
+    /// Only report files larger than this, like 500M, 2GiB or 1.5TB
+    #[clap(long, parse(try_from_str = parse_size))]
+    pub largebytes: Option<u64>,
+
+    /// Include hidden files and directories
+    #[clap(long, conflicts_with = "no-hidden")]
+    pub hidden: bool,
+
+    /// Skip hidden files and directories
+    #[clap(long)]
+    pub no_hidden: bool,
+
+    /// Walk into symlinked directories
+    #[clap(long)]
+    pub follow_symlinks: bool,
+
+    /// Stay on the filesystem of each directory
+    #[clap(short = 'x', long)]
+    pub one_file_system: bool,
+
+    /// Number of threads walking each directory
+    #[clap(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Rank by file count instead of bytes
+    #[clap(long)]
+    pub files: bool,
+
+    /// Print sizes in powers of 1000 (si: kB, MB) or 1024 (iec: KiB, MiB)
+    #[clap(long, possible_values = ["si", "iec"])]
+    pub units: Option<SizeUnits>,
+
+    /// Prompt to delete the largest files after the report
+    #[clap(short, long)]
+    pub delete: bool,
+
+    /// Never delete this path or anything in it without typing it out (repeatable).
+    /// Adds to the protected paths from the config file.
+    #[clap(long)]
+    pub protect: Vec<PathBuf>,
+
+    /// Only report what deleting, compressing, moving, restoring or purging would do, without touching anything
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show the deletion audit log, most recent last
+    History {
+        /// Only show deletions of this path or paths under it
+        path: Option<PathBuf>,
+
+        /// Number of entries to show
+        #[clap(short, long, default_value = "50")]
+        limit: usize,
+    },
+
+    /// Review a cleanup plan exported from the app, or run it
+    Plan {
+        /// The plan file
+        file: PathBuf,
+
+        /// Delete everything in the plan
+        #[clap(long)]
+        run: bool,
+    },
+
+    /// List what this app moved to the trash, or restore or purge it
+    Trash {
+        /// Put the most recently trashed item at this path back
+        #[clap(long, conflicts_with = "purge")]
+        restore: Option<PathBuf>,
+
+        /// Remove the most recently trashed item at this path from the trash for good
+        #[clap(long)]
+        purge: Option<PathBuf>,
+    },
+
+    /// Compress files in place and pack directories into .tar.zst archives next
+    /// to them, removing the originals once the copies are checked
+    Compress {
+        /// Files and directories to shrink
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// How files are compressed. Directories are always archived with zstd.
+        #[clap(long, default_value = "gzip", possible_values = ["gzip", "zstd"])]
+        format: Compression,
+    },
+
+    /// Scan, then delete what a rules file picks. Only reports what would be
+    /// deleted unless --run is given.
+    Clean {
+        /// The rules file
+        rules: PathBuf,
+
+        /// Delete what the rules pick
+        #[clap(long)]
+        run: bool,
+
+        /// Also save what the rules pick as a cleanup plan
+        #[clap(long)]
+        save_plan: Option<PathBuf>,
+    },
+
+    /// Scan and compare against thresholds, printing a TOML summary and exiting
+    /// with 1 when any is crossed. Thresholds come from the config's [check]
+    /// table and can be overridden here.
+    Check {
+        /// Directories to check, instead of the configured ones
+        dirs: Vec<PathBuf>,
+
+        /// Alert when a disk holding a checked directory has less than this percentage free
+        #[clap(long)]
+        min_free: Option<f64>,
+
+        /// Alert when a checked directory is larger than this, like 50G
+        #[clap(long, parse(try_from_str = parse_size))]
+        max_size: Option<u64>,
+
+        /// Alert when a checked directory grew by more than this since the last check
+        #[clap(long, parse(try_from_str = parse_size))]
+        max_growth: Option<u64>,
+    },
+
+    /// Move a file or directory to another directory, like one on a second disk
+    Relocate {
+        /// The file or directory to move
+        path: PathBuf,
+
+        /// The directory to move it into
+        destination: PathBuf,
+
+        /// Leave a symlink to the new place behind
+        #[clap(long)]
+        symlink: bool,
+    },
+}
+
+impl Args {
+    /// The config's scan settings with any flags given on the command line applied
+    pub fn scan_settings(&self, config: &Config) -> ScanSettings {
+        let mut settings = config.scan_settings();
+
+        if !self.dirs.is_empty() {
+            settings.dirs = self.dirs.clone();
+        }
+        if !self.ignore.is_empty() {
+            settings.ignore = self.ignore.clone();
+        }
+        if !self.exclude_from.is_empty() {
+            settings.exclude_files = self.exclude_from.clone();
+        }
+        if self.gitignore {
+            settings.use_gitignore = true;
+        }
+        if !self.developer_pattern.is_empty() {
+            settings.developer_patterns = self.developer_pattern.clone();
+        }
+        if let Some(nlargest) = self.nlargest {
+            settings.nlargest = nlargest;
+        }
+        if let Some(largebytes) = self.largebytes {
+            settings.largebytes = largebytes;
+        }
+        if self.hidden {
+            settings.hidden = true;
+        }
+        if self.no_hidden {
+            settings.hidden = false;
+        }
+        if self.follow_symlinks {
+            settings.follow_symlinks = true;
+        }
+        if self.one_file_system {
+            settings.one_filesystem = true;
+        }
+        if let Some(threads) = self.threads {
+            settings.threads = threads;
+        }
+        if self.files {
+            settings.mode = UsageMode::Files;
+        }
+
+        settings
+    }
+
+    /// What deleting, compressing and moving run with: `--dry-run`, the
+    /// config's protected paths plus any given with `--protect`, and its units
+    pub fn action_settings(&self, config: &Config) -> ActionSettings {
+        ActionSettings {
+            dry_run: self.dry_run,
+            protected: ProtectedPaths::new(&[config.protected_paths.clone(), self.protect.clone()].concat()),
+            units: config.units,
+        }
+    }
+}
+
+/// Loads the config, with the size units given on the command line applied
+fn setup(args: &Args) -> Config {
+    let mut config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(units) = args.units {
+        config.units = units;
+    }
+
+    if args.dry_run {
+        eprintln!("{}", "Dry run: nothing will be deleted, compressed, moved, restored or purged".bright_yellow());
+    }
+
+    config
+}
+
+pub fn run(args: Args) {
+    let config = setup(&args);
+
+    let settings = args.scan_settings(&config);
+
+    let analyzer = match Analyzer::new(settings) {
+        Ok(analyzer) => analyzer,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    analyzer.set_progress_interval(Duration::from_millis(500));
+    let reporter = spawn_progress_reporter(analyzer.subscribe(), config.units);
+
+    let scan = analyzer.analyze();
+
+    reporter.join().expect("Progress reporter panicked");
+
+    print_report(&scan, config.units);
+
+    if args.delete {
+        prompt_delete(&scan, &args.action_settings(&config));
+    }
+}
+
+pub fn run_command(command: Command, args: &Args) {
+    let config = setup(args);
+    let actions = args.action_settings(&config);
+
+    match command {
+        Command::History { path, limit } => print_history(path, limit, config.units),
+        Command::Plan { file, run } => run_plan(&file, run, &actions),
+        Command::Trash { restore, purge } => manage_trash(restore, purge, args.dry_run, config.units),
+        Command::Compress { paths, format } => compress(&paths, format, &actions),
+        Command::Clean { rules, run, save_plan } => clean(&rules, run, save_plan.as_deref(), args, &config, &actions),
+        Command::Check { dirs, min_free, max_size, max_growth } => {
+            let thresholds = Thresholds {
+                min_free_percent: min_free.or(config.check.min_free_percent),
+                max_size: max_size.or(config.check.max_size),
+                max_growth: max_growth.or(config.check.max_growth),
+            };
+            run_check(&dirs, &thresholds, args, &config)
+        }
+        Command::Relocate { path, destination, symlink } => move_path(&path, &destination, symlink, &actions),
+    }
+}
+
+fn compress(paths: &[PathBuf], format: Compression, actions: &ActionSettings) {
+    let mut saved = 0;
+    let mut failed = 0;
+
+    for path in paths {
+        let (result, done, would) = match path.is_dir() {
+            true => (archive_dir(path, "", actions), "Archived", "Would archive"),
+            false => (compress_file(path, format, "", actions), "Compressed", "Would compress"),
+        };
+
+        match result {
+            Ok(shrunk) => {
+                match actions.dry_run {
+                    true => println!("{} {}", would, shrunk.summary(actions.units)),
+                    false => println!("{} {}", done, shrunk.summary(actions.units)),
+                }
+                saved += shrunk.saved();
+            }
+            Err(e) => {
+                println!("{}", format!("Unable to compress {}: {}", path.display(), e).bright_red());
+                failed += 1;
+            }
+        }
+    }
+
+    println!();
+    print_reclaimed(saved, actions);
+    if failed > 0 {
+        println!("{} of {} paths could not be compressed", failed, paths.len());
+        std::process::exit(1);
+    }
+}
+
+fn clean(rules_file: &Path, run: bool, save_plan: Option<&Path>, args: &Args, config: &Config, actions: &ActionSettings) {
+    let rules = match RuleSet::load(rules_file) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let mut settings = args.scan_settings(config);
+    if !rules.dirs.is_empty() {
+        settings.dirs = rules.dirs.clone();
+    }
+
+    let scan = match scan(settings.clone(), |_| {}) {
+        Ok(scan) => scan,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let matches = match rules.evaluate(&scan, &settings, &actions.protected) {
+        Ok(matches) => matches,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    for (i, rule) in rules.rules.iter().enumerate() {
+        let picked: Vec<_> = matches.iter().filter(|m| m.rule == i).collect();
+        let bytes = picked.iter().map(|m| m.bytes).sum();
+        println!("{}: {} paths ({}), {}", rule.label(), picked.len(), bytes_to_human(bytes, actions.units), rule.mode);
+    }
+    println!();
+
+    let plan = rules.plan(&scan.id, &matches);
+    if let Some(file) = save_plan {
+        if let Err(e) = plan.save(file) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+        println!("Saved the plan to {}", file.display());
+    }
+
+    if plan.is_empty() {
+        println!("Nothing to delete");
+        return;
+    }
+
+    if !run && !actions.dry_run {
+        eprintln!("{}", "Dry run: nothing will be deleted, pass --run to delete".bright_yellow());
+    }
+    let actions = ActionSettings {
+        dry_run: actions.dry_run || !run,
+        ..actions.clone()
+    };
+    execute_plan(&plan, &actions);
+}
+
+fn run_check(dirs: &[PathBuf], thresholds: &Thresholds, args: &Args, config: &Config) {
+    if *thresholds == Thresholds::default() {
+        eprintln!("No thresholds to check, set some in the config's [check] table or with flags");
+        std::process::exit(2);
+    }
+
+    let mut settings = args.scan_settings(config);
+    settings.mode = UsageMode::Bytes;
+    if !dirs.is_empty() {
+        settings.dirs = dirs.to_vec();
+    }
+
+    let scan = match scan(settings, |_| {}) {
+        Ok(scan) => scan,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let snapshot_path = Snapshot::default_location();
+    let mut snapshot = match snapshot_path.as_deref().map(Snapshot::load).transpose() {
+        Ok(snapshot) => snapshot.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let summary = CheckSummary::new(check(&scan, thresholds, &snapshot));
+
+    snapshot.update(&scan);
+    if let Some(path) = snapshot_path {
+        if let Err(e) = snapshot.save(&path) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    print!("{}", summary.to_toml());
+    if summary.failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn move_path(path: &Path, destination: &Path, symlink: bool, actions: &ActionSettings) {
+    if let Some(free) = free_space(destination) {
+        println!("{} free on {}", bytes_to_human(free, actions.units), destination.display());
+    }
+
+    let result = relocate(path, destination, symlink, "", actions, &mut |copied| {
+        eprint!("\r\x1b[2K  Copied {}", bytes_to_human(copied, actions.units));
+        let _ = io::stderr().flush();
+    });
+    clear_progress_line();
+
+    let relocated = match result {
+        Ok(relocated) => relocated,
+        Err(e) => {
+            eprintln!("{}", format!("Unable to move {}: {}", path.display(), e).bright_red());
+            std::process::exit(1);
+        }
+    };
+
+    let verb = if actions.dry_run { "Would move" } else { "Moved" };
+    println!(
+        "{} {} ({}) to {}",
+        verb,
+        relocated.path.display(),
+        bytes_to_human(relocated.bytes, actions.units),
+        relocated.destination.display()
+    );
+    if relocated.linked {
+        println!("{} links to it", relocated.path.display());
+    }
+}
+
+fn run_plan(file: &Path, run: bool, actions: &ActionSettings) {
+    let plan = match CleanupPlan::load(file) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if !run {
+        for item in plan.items.iter() {
+            let marker = if actions.protected.is_protected(&item.path) {
+                " [protected, will be refused]".bright_red().to_string()
+            } else if !plan.outermost().iter().any(|outer| outer.path == item.path) {
+                " [deleted with its parent]".to_string()
+            } else {
+                String::new()
+            };
+            println!("{} {} ({}){}", item.mode, item.path.display(), bytes_to_human(item.bytes, actions.units), marker);
+        }
+        println!();
+        println!("{} items, {} to reclaim", plan.items.len(), bytes_to_human(plan.total_bytes(), actions.units));
+        return;
+    }
+
+    execute_plan(&plan, actions);
+}
+
+/// Deletes everything in `plan`, exiting with 1 if anything couldn't be deleted
+fn execute_plan(plan: &CleanupPlan, actions: &ActionSettings) {
+    let total = plan.outermost().len();
+    let mut done = 0;
+    let results = plan.execute(actions, |progress| print_remove_progress(progress, actions.units), |result| {
+        clear_progress_line();
+        done += 1;
+        match &result.result {
+            Ok(_) if actions.dry_run => println!("[{}/{}] Would delete {}", done, total, result.item.path.display()),
+            Ok(_) => println!("[{}/{}] Deleted {}", done, total, result.item.path.display()),
+            Err(e) => println!("{}", format!("[{}/{}] Unable to delete {}: {}", done, total, result.item.path.display(), e).bright_red()),
+        }
+    });
+
+    let failed = results.iter().filter(|r| r.result.is_err()).count();
+
+    println!();
+    print_reclaimed(reclaimed_bytes(&results), actions);
+    if failed > 0 {
+        println!("{} of {} items could not be deleted", failed, total);
+        std::process::exit(1);
+    }
+}
+
+fn manage_trash(restore: Option<PathBuf>, purge: Option<PathBuf>, dry_run: bool, units: SizeUnits) {
+    let items = match trashed_items() {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let (path, purging) = match (restore, purge) {
+        (Some(path), _) => (path, false),
+        (_, Some(path)) => (path, true),
+        (None, None) => {
+            if items.is_empty() {
+                println!("Nothing this app moved to the trash is left in it");
+            }
+            for item in items.iter() {
+                println!(
+                    "{} {} ({})",
+                    item.deleted_at.format("%Y-%m-%d %T"),
+                    item.path.display(),
+                    bytes_to_human(item.len, units)
+                );
+            }
+            return;
+        }
+    };
+
+    let item = match items.into_iter().find(|item| item.path == path) {
+        Some(item) => item,
+        None => {
+            eprintln!("{} isn't in the trash, or wasn't moved there by this app", path.display());
+            std::process::exit(1);
+        }
+    };
+
+    let len = item.len;
+    let result = match purging {
+        true => restore::purge(item, "", dry_run),
+        false => restore::restore(item, "", dry_run),
+    };
+    if let Err(e) = result {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+
+    match (dry_run, purging) {
+        (true, true) => println!("Would purge {} ({})", path.display(), bytes_to_human(len, units)),
+        (true, false) => println!("Would restore {} ({})", path.display(), bytes_to_human(len, units)),
+        (false, true) => println!("Purged {} ({})", path.display(), bytes_to_human(len, units)),
+        (false, false) => println!("Restored {} ({})", path.display(), bytes_to_human(len, units)),
+    }
+}
+
+fn print_history(path: Option<PathBuf>, limit: usize, units: SizeUnits) {
+    let log = match AuditLog::default_location() {
+        Some(log) => log,
+        None => {
+            eprintln!("No data directory to keep the audit log in");
+            std::process::exit(2);
+        }
+    };
+
+    let entries = match log.read() {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Unable to read audit log {}: {}", log.path().display(), e);
+            std::process::exit(2);
+        }
+    };
+
+    let entries: Vec<_> = entries
+        .iter()
+        .filter(|entry| match &path {
+            Some(path) => entry.path.starts_with(path),
+            None => true,
+        })
+        .collect();
+
+    if entries.is_empty() {
+        println!("No deletions recorded in {}", log.path().display());
+        return;
+    }
+
+    for entry in entries.iter().skip(entries.len().saturating_sub(limit)) {
+        match entry.error {
+            Some(_) => println!("{}", entry.summary(units).bright_red()),
+            None => println!("{}", entry.summary(units)),
+        }
+    }
+}
+
+pub fn print_report(scan: &Scan, units: SizeUnits) {
+    println!("{}", "\n-- Usage Report --\n".bright_yellow());
+
+    let mut sys = System::new_all();
+    sys.refresh_all();
+
+    println!("{}", "Totals:".bright_green());
+    println!("  Disk usage: {}", bytes_to_human(scan.total_bytes, units));
+    println!("  Files: {} ({} directories)", scan.num_files, scan.num_dirs);
+
+    println!();
+
+    let roots = scan.root_usage();
+    if roots.len() > 1 {
+        println!("{}", "Roots:".bright_green());
+        for (root, usage) in roots.iter() {
+            println!("  {}: {}", root.display(), format_usage(*usage, scan.mode, units));
+        }
+
+        println!();
+    }
+
+    println!("{}", "Current disk usage:".bright_green());
+    for disk in sys.disks() {
+        let p = (disk.available_space() as f64 / disk.total_space() as f64) * 100.0;
+        println!(
+            "  {} ({} free ({:.2}%) , {} total)",
+            disk.name().to_string_lossy(),
+            bytes_to_human(disk.available_space(), units),
+            p,
+            bytes_to_human(disk.total_space(), units)
+        );
+        if let Some((total, free)) = inode_usage(disk.mount_point()) {
+            let p = (free as f64 / total as f64) * 100.0;
+            println!(
+                "    inodes: {} free ({:.2}%) , {} total",
+                free,
+                p,
+                total
+            );
+        }
+    }
+
+    println!();
+
+    let mode = scan.mode;
+    let print_type = |type_name: &str, len: u64| {
+        println!("  {}: {}", type_name, format_usage(len, mode, units));
+    };
+
+    println!("{}", "File types:".bright_green());
+    print_type("Images", scan.total_images);
+    print_type("Videos", scan.total_videos);
+    print_type("Music", scan.total_music);
+    print_type("Documents", scan.total_documents);
+    print_type("Archives", scan.total_archives);
+    print_type("Binaries", scan.total_binaries);
+    print_type("Other", scan.total_other);
+
+    println!();
+
+    if !scan.exclusions.is_empty() {
+        println!("{}", "Excluded:".bright_green());
+        for (rule, count) in scan.exclusions.iter() {
+            println!("  {}: {} entries", rule, count);
+        }
+
+        println!();
+    }
+
+    if !scan.errors.is_empty() {
+        println!("{}", "Errors:".bright_red());
+        println!("  {} paths could not be read, totals may be incomplete", scan.errors.len());
+        for summary in summarize_errors(&scan.errors).iter().take(10) {
+            println!("  {}", summary);
+        }
+
+        println!();
+    }
+
+    let junk = summarize_junk(&scan.junk);
+    if !junk.is_empty() {
+        println!("{}", "Junk:".bright_green());
+        for (kind, count, len) in junk {
+            println!("  {}: {} ({})", kind, count, bytes_to_human(len, units));
+        }
+
+        println!();
+    }
+
+    if !scan.broken_symlinks.is_empty() {
+        println!("{}", "Broken symlinks:".bright_red());
+        for symlink in scan.broken_symlinks.iter().take(10) {
+            println!("  {}", symlink);
+        }
+        if scan.broken_symlinks.len() > 10 {
+            println!("  ... and {} more", scan.broken_symlinks.len() - 10);
+        }
+
+        println!();
+    }
+
+    match mode {
+        UsageMode::Bytes => println!("{}", "Top files:".bright_green()),
+        UsageMode::Files => println!("{}", "Top directories by file count:".bright_green()),
+    }
+    for file in scan.largest_files.iter() {
+        println!("{} ({})", file.0.display().to_string().bright_white(), format_usage(file.1, mode, units).bold());
+    }
+
+    if mode == UsageMode::Bytes {
+        println!();
+        println!("{}", "Top directories:".bright_green());
+        for dir in scan.largest_dirs.iter() {
+            println!("{} ({})", dir.0.display().to_string().bright_white(), format_usage(dir.1, mode, units).bold());
+        }
+    }
+}
+
+pub fn prompt_delete(scan: &Scan, actions: &ActionSettings) {
+    let mut total_deleted: u64 = 0;
+    let mut deleted = 0;
+
+    let selections = &[
+        "Keep",
+        "Exit",
+        "Delete (trash)",
+        "Delete (force)"
+    ];
+    for file in scan.largest_files.iter() {
+        let protected = actions.protected.is_protected(&file.0);
+        let marker = match protected {
+            true => " [protected]".bright_red().to_string(),
+            false => String::new(),
+        };
+
+        println!();
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Delete {} ({}){}?", file.0.display(), format_usage(file.1, scan.mode, actions.units), marker))
+            .default(0)
+            .items(&selections[..])
+            .interact_opt()
+            .unwrap();
+
+        if let Some(selection) = selection {
+            if selection == 1 {
+                // Exit on exit
+                break
+            }
+
+            if (selection == 2 || selection == 3) && protected && !confirm_protected(&file.0) {
+                println!("Kept {}", file.0.display());
+                continue;
+            }
+            let delete = if protected { delete_protected } else { delete };
+            // When ranking by file count there's no size to record
+            let bytes = scan.mode.bytes(file.1);
+
+            if selection == 2 {
+                println!("Deleting {}", file.0.display());
+                match delete(&file.0, bytes, DeleteMode::Trash, &scan.id, actions) {
+                    Ok(_) => {
+                        println!("{}", if actions.dry_run { "Would be deleted" } else { "Deleted!" });
+                        total_deleted += bytes;
+                        deleted += 1;
+                    },
+                    Err(e) => println!("Unable to delete: {}", e)
+                }
+            } else if selection == 3 {
+                println!("Deleting (force) {}", file.0.display());
+                let result = delete_with_progress(&file.0, bytes, DeleteMode::Force, &scan.id, protected, actions, &mut |progress| {
+                    print_remove_progress(progress, actions.units)
+                });
+                clear_progress_line();
+                match result {
+                    Ok(_) => {
+                        println!("{}", if actions.dry_run { "Would be deleted" } else { "Deleted!" });
+                        total_deleted += bytes;
+                        deleted += 1;
+                    },
+                    Err(e) => println!("Unable to delete: {}", e)
+                }
+            }
+        }
+    }
+
+    println!();
+    match scan.mode {
+        UsageMode::Bytes => print_reclaimed(total_deleted, actions),
+        UsageMode::Files if actions.dry_run => println!("Would delete {} directories", deleted),
+        UsageMode::Files => println!("Deleted {} directories", deleted),
+    }
+}
+
+/// Shows how far removing a directory tree has got on one line of stderr
+fn print_remove_progress(progress: &RemoveProgress, units: SizeUnits) {
+    eprint!("\r\x1b[2K  Removed {} files ({})", progress.files, bytes_to_human(progress.bytes, units));
+    let _ = io::stderr().flush();
+}
+
+fn clear_progress_line() {
+    eprint!("\r\x1b[2K");
+}
+
+fn print_reclaimed(bytes: u64, actions: &ActionSettings) {
+    match actions.dry_run {
+        true => println!("Would reclaim {} of disk space", bytes_to_human(bytes, actions.units)),
+        false => println!("Reclaimed {} of disk space", bytes_to_human(bytes, actions.units)),
+    }
+}
+
+/// Protected paths are only deleted after typing them out in full
+fn confirm_protected(path: &Path) -> bool {
+    println!("{}", format!("{} is protected, type its full path to delete it anyway", path.display()).bright_red());
+
+    let typed: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Path")
+        .allow_empty(true)
+        .interact_text()
+        .unwrap_or_default();
+
+    typed == path.to_string_lossy()
+}
+
+
+/// Prints a progress line to stderr for every update until the scan finishes
+fn spawn_progress_reporter(events: Receiver<ProgressEvent>, units: SizeUnits) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let started_at = Instant::now();
+        let mut expected = None;
+        let mut last = None;
+
+        for event in events {
+            match event {
+                ProgressEvent::Started { expected_bytes, .. } => expected = expected_bytes,
+                ProgressEvent::Update { bytes, files, .. } => {
+                    let progress = Progress {
+                        bytes,
+                        files,
+                        expected_bytes: expected,
+                        elapsed: started_at.elapsed(),
+                    };
+
+                    eprint!("\r\x1b[2K{}", progress.summary(units));
+                    let _ = io::stderr().flush();
+                    last = Some(progress);
+                }
+                ProgressEvent::Finished => break,
+            }
+        }
+
+        // The estimate is only an upper bound, so the final totals go without it
+        if let Some(mut progress) = last {
+            progress.expected_bytes = None;
+            eprintln!("\r\x1b[2K{}", progress.summary(units));
+        }
+    })
+}
@@ -0,0 +1,471 @@
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use filetime::FileTime;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{record, AuditAction, AuditEntry},
+    delete::{remove, ActionSettings},
+    protect::ProtectedPaths,
+    utils::{bytes_to_human, SizeUnits},
+};
+
+/// zstd's default level, a good trade between speed and size
+const ZSTD_LEVEL: i32 = 3;
+
+/// Extensions of files that are already compressed, so compressing them again
+/// wouldn't save anything
+const COMPRESSED_EXTENSIONS: &[&str] = &["gz", "tgz", "zst", "xz", "bz2", "lz4", "zip", "7z", "rar"];
+
+/// How a file is compressed in place
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Added to the file name of the compressed file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Compresses everything in `input` into `out`, returning `out` once it's all written
+    fn encode<W: Write>(&self, input: &mut dyn Read, out: W) -> io::Result<W> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(out, flate2::Compression::default());
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(out, ZSTD_LEVEL)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn decoder<'a, R: Read + 'a>(&self, input: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(GzDecoder::new(input)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(input)?),
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression \"{}\", expected gzip or zstd", s)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// What compressing a file or archiving a directory did
+#[derive(Clone, Debug)]
+pub struct Shrunk {
+    /// The file or directory that was replaced
+    pub path: PathBuf,
+    /// The compressed file or archive replacing it
+    pub output: PathBuf,
+    /// Bytes the original took up
+    pub before: u64,
+    /// Bytes the compressed file or archive takes up
+    pub after: u64,
+}
+
+impl Shrunk {
+    /// Disk space given back by replacing the original
+    pub fn saved(&self) -> u64 {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+impl Shrunk {
+    /// What was replaced with what, sizes in `units`
+    pub fn summary(&self, units: SizeUnits) -> String {
+        format!(
+            "{} ({}) to {} ({}), saving {}",
+            self.path.display(),
+            bytes_to_human(self.before, units),
+            self.output.display(),
+            bytes_to_human(self.after, units),
+            bytes_to_human(self.saved(), units)
+        )
+    }
+}
+
+/// Compresses the file at `path` next to it, like `gzip` does, and removes the
+/// original once the compressed copy is checked to decompress to the same bytes.
+/// The outcome is recorded in the audit log.
+///
+/// During a dry run the file is compressed without writing anything, to report
+/// what it would save.
+pub fn compress_file(
+    path: &Path,
+    compression: Compression,
+    scan_id: &str,
+    settings: &ActionSettings,
+) -> Result<Shrunk, String> {
+    let output = with_suffix(path, compression.extension());
+    let result = check_file(path, &settings.protected).and_then(|meta| {
+        let after = match settings.dry_run {
+            true => compressed_len(path, compression),
+            false => write_compressed(path, &output, &meta, compression),
+        };
+
+        after.map(|after| Shrunk {
+            path: path.to_path_buf(),
+            output,
+            before: meta.len(),
+            after,
+        })
+    });
+
+    log(path, AuditAction::Compress, scan_id, settings, result)
+}
+
+/// Packs the directory at `path` into a `.tar.zst` archive next to it and
+/// removes the directory once everything in the archive is checked against
+/// it. The outcome is recorded in the audit log.
+///
+/// Symlinks are archived as links. During a dry run the archive is built
+/// without writing anything, to report what it would save.
+pub fn archive_dir(path: &Path, scan_id: &str, settings: &ActionSettings) -> Result<Shrunk, String> {
+    let output = with_suffix(path, "tar.zst");
+    let result = check_dir(path, &settings.protected).and_then(|name| {
+        let before = tree_contents(path).map_err(|e| e.to_string())?.bytes;
+        let after = match settings.dry_run {
+            true => write_archive(path, &name, Counter(0)).map(|counter| counter.0).map_err(|e| e.to_string()),
+            false => write_verified_archive(path, &name, &output, settings),
+        }?;
+
+        Ok(Shrunk {
+            path: path.to_path_buf(),
+            output,
+            before,
+            after,
+        })
+    });
+
+    log(path, AuditAction::Archive, scan_id, settings, result)
+}
+
+fn check_file(path: &Path, protected: &ProtectedPaths) -> Result<fs::Metadata, String> {
+    if protected.is_protected(path) {
+        return Err(format!("{} is protected", path.display()));
+    }
+
+    let meta = path.symlink_metadata().map_err(|e| e.to_string())?;
+    if !meta.is_file() {
+        return Err(format!("{} is not a regular file", path.display()));
+    }
+
+    let extension = path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    if extension.is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.as_str())) {
+        return Err(format!("{} is already compressed", path.display()));
+    }
+
+    Ok(meta)
+}
+
+/// The name the directory at `path` gets inside its archive
+fn check_dir(path: &Path, protected: &ProtectedPaths) -> Result<PathBuf, String> {
+    if protected.is_protected(path) {
+        return Err(format!("{} is protected", path.display()));
+    }
+    // Checked before archiving, since removing the directory afterwards would be refused
+    if let Some(inside) = protected.protected_inside(path) {
+        return Err(format!("{} holds the protected {}", path.display(), inside.display()));
+    }
+
+    let meta = path.symlink_metadata().map_err(|e| e.to_string())?;
+    if !meta.is_dir() {
+        return Err(format!("{} is not a directory", path.display()));
+    }
+
+    path.file_name()
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("{} has no name to archive it under", path.display()))
+}
+
+/// `path` with `.suffix` added to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Size the file at `path` would be compressed
+fn compressed_len(path: &Path, compression: Compression) -> Result<u64, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+
+    compression.encode(&mut file, Counter(0)).map(|counter| counter.0).map_err(|e| e.to_string())
+}
+
+/// Compresses `path` into `output`, checks it and removes `path`, returning the compressed size
+fn write_compressed(path: &Path, output: &Path, meta: &fs::Metadata, compression: Compression) -> Result<u64, String> {
+    let out = create_new(output).map_err(|e| format!("Unable to create {}: {}", output.display(), e))?;
+    let written = {
+        let result = (|| -> io::Result<u64> {
+            let out = compression.encode(&mut File::open(path)?, out)?;
+            out.sync_all()?;
+
+            let mut decompressed = compression.decoder(File::open(output)?)?;
+            if !same_contents(&mut decompressed, &mut File::open(path)?)? {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "the compressed copy doesn't match"));
+            }
+
+            fs::set_permissions(output, meta.permissions())?;
+            filetime::set_file_mtime(output, FileTime::from_last_modification_time(meta))?;
+
+            Ok(out.metadata()?.len())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(output);
+        }
+        result.map_err(|e| format!("Unable to compress to {}: {}", output.display(), e))?
+    };
+
+    fs::remove_file(path)
+        .map_err(|e| format!("Compressed to {}, but couldn't remove the original: {}", output.display(), e))?;
+
+    Ok(written)
+}
+
+/// Archives `dir` into `output`, checks it and removes `dir`, returning the archive's size
+fn write_verified_archive(dir: &Path, name: &Path, output: &Path, settings: &ActionSettings) -> Result<u64, String> {
+    let out = create_new(output).map_err(|e| format!("Unable to create {}: {}", output.display(), e))?;
+    let written = {
+        let result = (|| -> io::Result<u64> {
+            let out = write_archive(dir, name, out)?;
+            out.sync_all()?;
+            verify_archive(output, dir)?;
+
+            Ok(out.metadata()?.len())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(output);
+        }
+        result.map_err(|e| format!("Unable to archive to {}: {}", output.display(), e))?
+    };
+
+    remove(dir, settings, &mut |_| {}).map_err(|e| format!("Archived to {}, but {}", output.display(), e))?;
+
+    Ok(written)
+}
+
+fn create_new(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+fn write_archive<W: Write>(dir: &Path, name: &Path, out: W) -> io::Result<W> {
+    let mut builder = tar::Builder::new(zstd::Encoder::new(out, ZSTD_LEVEL)?);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(name, dir)?;
+
+    builder.into_inner()?.finish()
+}
+
+/// Checks every file and symlink in the archive at `output` matches the one in
+/// `dir`, and that nothing in `dir` is missing from it
+fn verify_archive(output: &Path, dir: &Path) -> io::Result<()> {
+    let mismatch = |path: &Path, what: &str| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} {} in the archive", path.display(), what))
+    };
+
+    let parent = dir.parent().unwrap_or(dir);
+    let mut archive = tar::Archive::new(zstd::Decoder::new(File::open(output)?)?);
+    let mut archived = TreeContents::default();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = parent.join(entry.path()?);
+        let kind = entry.header().entry_type();
+
+        if kind.is_file() {
+            if !same_contents(&mut entry, &mut File::open(&path)?)? {
+                return Err(mismatch(&path, "doesn't match"));
+            }
+            archived.files += 1;
+        } else if kind.is_symlink() {
+            if entry.link_name()?.as_deref() != Some(fs::read_link(&path)?.as_path()) {
+                return Err(mismatch(&path, "points somewhere else"));
+            }
+            archived.links += 1;
+        }
+    }
+
+    let contents = tree_contents(dir)?;
+    if (archived.files, archived.links) != (contents.files, contents.links) {
+        return Err(mismatch(dir, "is missing entries"));
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct TreeContents {
+    files: u64,
+    links: u64,
+    /// Bytes in the files
+    bytes: u64,
+}
+
+/// Counts the files and symlinks under `dir`, without following symlinks
+fn tree_contents(dir: &Path) -> io::Result<TreeContents> {
+    let mut contents = TreeContents::default();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let meta = entry.path().symlink_metadata()?;
+
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else if meta.is_file() {
+                contents.files += 1;
+                contents.bytes += meta.len();
+            } else if meta.file_type().is_symlink() {
+                contents.links += 1;
+            }
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Whether `a` and `b` read out the same bytes
+pub(crate) fn same_contents(a: &mut dyn Read, b: &mut dyn Read) -> io::Result<bool> {
+    let mut buf_a = vec![0; 64 * 1024];
+    let mut buf_b = vec![0; 64 * 1024];
+
+    loop {
+        let read_a = read_full(a, &mut buf_a)?;
+        let read_b = read_full(b, &mut buf_b)?;
+        if buf_a[..read_a] != buf_b[..read_b] {
+            return Ok(false);
+        }
+        if read_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads until `buf` is full or the input ends
+fn read_full(input: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+/// Throws away what's written, counting the bytes
+struct Counter(u64);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records the outcome in the audit log, with the original's size
+fn log(
+    path: &Path,
+    action: AuditAction,
+    scan_id: &str,
+    settings: &ActionSettings,
+    result: Result<Shrunk, String>,
+) -> Result<Shrunk, String> {
+    let len = result.as_ref().map(|shrunk| shrunk.before).unwrap_or(0);
+    let outcome = result.as_ref().map(|_| ()).map_err(|e| e.clone());
+
+    if let Err(e) = record(&AuditEntry::new(path, len, action, scan_id, &outcome, settings.dry_run)) {
+        let outcome = match &result {
+            Ok(shrunk) => format!("Saved {}", bytes_to_human(shrunk.saved(), settings.units)),
+            Err(error) => error.clone(),
+        };
+        return Err(format!("{}, but {}", outcome, e));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{check_file, write_compressed, write_verified_archive, Compression};
+    use crate::delete::ActionSettings;
+
+    #[test]
+    fn compress_and_archive() {
+        let root = std::env::temp_dir().join(format!("disk-usage-compress-{}", std::process::id()));
+        fs::create_dir_all(root.join("logs/old")).unwrap();
+        fs::write(root.join("logs/a.log"), "a\n".repeat(10_000)).unwrap();
+        fs::write(root.join("logs/old/b.log"), "b\n".repeat(10_000)).unwrap();
+
+        let settings = ActionSettings::default();
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let file = root.join(format!("{}.log", compression));
+            let output = root.join(format!("{}.log.{}", compression, compression.extension()));
+            fs::write(&file, "line\n".repeat(10_000)).unwrap();
+
+            let meta = check_file(&file, &settings.protected).unwrap();
+            let after = write_compressed(&file, &output, &meta, compression).unwrap();
+            assert!(!file.exists());
+            assert_eq!(after, fs::metadata(&output).unwrap().len());
+            assert!(after < 1_000);
+            assert!(check_file(&output, &settings.protected).is_err());
+        }
+
+        let output = root.join("logs.tar.zst");
+        let after = write_verified_archive(&root.join("logs"), "logs".as_ref(), &output, &settings).unwrap();
+        assert!(!root.join("logs").exists());
+        assert_eq!(after, fs::metadata(&output).unwrap().len());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use disk_usage::{
+    utils::{parse_size, SizeUnits},
+    ScanSettings, UsageMode,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+/// Settings persisted between runs, in TOML
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub roots: Vec<PathBuf>,
+    pub ignore: Vec<String>,
+    pub exclude_files: Vec<PathBuf>,
+    pub use_gitignore: bool,
+    pub developer_patterns: Vec<String>,
+    pub nlargest: u64,
+    /// A byte count or a size string like "50 MiB"
+    #[serde(deserialize_with = "deserialize_size")]
+    pub largebytes: u64,
+    pub hidden: bool,
+    pub follow_symlinks: bool,
+    pub one_filesystem: bool,
+    pub threads: usize,
+    /// Paths that are never deleted without typing them out, on top of the system directories
+    pub protected_paths: Vec<PathBuf>,
+    pub mode: UsageMode,
+    pub units: SizeUnits,
+    pub theme: Theme,
+    pub window_size: [f32; 2],
+    /// Thresholds for the check command. Last, since TOML tables follow plain values.
+    pub check: CheckConfig,
+}
+
+/// Thresholds the check command alerts on, unless overridden by flags
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CheckConfig {
+    pub min_free_percent: Option<f64>,
+    /// A byte count or a size string like "50 GiB"
+    #[serde(deserialize_with = "deserialize_optional_size")]
+    pub max_size: Option<u64>,
+    /// Growth since the last check, a byte count or a size string
+    #[serde(deserialize_with = "deserialize_optional_size")]
+    pub max_growth: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let scan = ScanSettings::default();
+
+        Config {
+            roots: dirs::home_dir().into_iter().collect(),
+            ignore: scan.ignore,
+            exclude_files: scan.exclude_files,
+            use_gitignore: scan.use_gitignore,
+            developer_patterns: scan.developer_patterns,
+            nlargest: scan.nlargest,
+            largebytes: scan.largebytes,
+            hidden: scan.hidden,
+            follow_symlinks: scan.follow_symlinks,
+            one_filesystem: scan.one_filesystem,
+            threads: scan.threads,
+            protected_paths: vec![],
+            mode: scan.mode,
+            units: SizeUnits::Iec,
+            theme: Theme::Dark,
+            window_size: [1024.0, 768.0],
+            check: CheckConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    #[cfg(feature = "gui")]
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Unable to access config {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config {}: {}", path.display(), e),
+            #[cfg(feature = "gui")]
+            ConfigError::Serialize(e) => write!(f, "Unable to write config: {}", e),
+        }
+    }
+}
+
+/// Accepts sizes written either as a number of bytes or as a size string
+fn deserialize_size<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(text) => parse_size(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+fn deserialize_optional_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_size(deserializer).map(Some)
+}
+
+/// `disk-usage/config.toml` in the user's config directory
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("disk-usage").join("config.toml"))
+}
+
+impl Config {
+    /// Loads the config at `path`, or the default location when `None`. A missing
+    /// file at the default location just means nothing has been saved yet.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => return Ok(Config::default()),
+            Err(e) => return Err(ConfigError::Io(path, e)),
+        };
+
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path, e))
+    }
+
+    /// Saves to `path`, or the default location when `None`
+    #[cfg(feature = "gui")]
+    pub fn save(&self, path: Option<&Path>) -> Result<(), ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_config_path() {
+                Some(path) => path,
+                None => return Ok(()),
+            },
+        };
+
+        let text = toml::to_string_pretty(self).map_err(ConfigError::Serialize)?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| ConfigError::Io(dir.to_path_buf(), e))?;
+        }
+
+        fs::write(&path, text).map_err(|e| ConfigError::Io(path, e))
+    }
+
+    pub fn scan_settings(&self) -> ScanSettings {
+        ScanSettings {
+            dirs: self.roots.clone(),
+            ignore: self.ignore.clone(),
+            exclude_files: self.exclude_files.clone(),
+            use_gitignore: self.use_gitignore,
+            developer_patterns: self.developer_patterns.clone(),
+            nlargest: self.nlargest,
+            largebytes: self.largebytes,
+            hidden: self.hidden,
+            follow_symlinks: self.follow_symlinks,
+            one_filesystem: self.one_filesystem,
+            threads: self.threads,
+            mode: self.mode,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use disk_usage::{utils::SizeUnits, UsageMode};
+
+    use super::{Config, Theme};
+
+    #[test]
+    fn partial_config_uses_defaults() {
+        let config: Config = toml::from_str("roots = [\"/var\"]\nmode = \"files\"\ntheme = \"light\"\n").unwrap();
+
+        assert_eq!(config.roots, vec![PathBuf::from("/var")]);
+        assert_eq!(config.mode, UsageMode::Files);
+        assert_eq!(config.theme, Theme::Light);
+        assert_eq!(config.nlargest, 100);
+        assert_eq!(config.developer_patterns, vec![String::from("**/node_modules")]);
+    }
+
+    #[test]
+    fn sizes_can_be_strings() {
+        let config: Config = toml::from_str("largebytes = \"500M\"\nunits = \"si\"\n").unwrap();
+        assert_eq!(config.largebytes, 500 * 1024 * 1024);
+        assert_eq!(config.units, SizeUnits::Si);
+
+        let config: Config = toml::from_str("largebytes = 1000\n").unwrap();
+        assert_eq!(config.largebytes, 1000);
+
+        assert!(toml::from_str::<Config>("largebytes = \"lots\"\n").is_err());
+
+        let config: Config = toml::from_str("[check]\nmax_size = \"2G\"\nmin_free_percent = 10.0\n").unwrap();
+        assert_eq!(config.check.max_size, Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(config.check.max_growth, None);
+        assert_eq!(config.check.min_free_percent, Some(10.0));
+    }
+}
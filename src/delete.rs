@@ -0,0 +1,277 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{record, AuditAction, AuditEntry},
+    protect::ProtectedPaths,
+    utils::{bytes_to_human, file_id, SizeUnits},
+};
+
+/// How a path is removed
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteMode {
+    /// Move to the trash so it can be restored
+    Trash,
+    /// Remove for good
+    Force,
+}
+
+impl fmt::Display for DeleteMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeleteMode::Trash => write!(f, "trash"),
+            DeleteMode::Force => write!(f, "force"),
+        }
+    }
+}
+
+/// What deleting, compressing and moving run with, fixed when each one starts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ActionSettings {
+    /// Only check and record what would happen, leaving the filesystem alone
+    pub dry_run: bool,
+    pub protected: ProtectedPaths,
+    /// Units for the sizes in messages
+    pub units: SizeUnits,
+}
+
+/// How far removing a directory tree has got
+#[derive(Clone, Debug, Default)]
+pub struct RemoveProgress {
+    /// The entry removed last
+    pub current: PathBuf,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Deletes `path` and records the outcome in the audit log. `len` is the size
+/// reported for it and `scan_id` the scan it was picked from, if any.
+///
+/// Paths protected in `settings` are refused, see `delete_protected`. During
+/// a dry run only checks the path is there, recording what would have happened.
+pub fn delete(path: &Path, len: u64, mode: DeleteMode, scan_id: &str, settings: &ActionSettings) -> Result<(), String> {
+    delete_with_progress(path, len, mode, scan_id, false, settings, &mut |_| {})
+}
+
+/// Like `delete`, but also deletes protected paths. Only for after the user
+/// confirmed deleting that particular path.
+pub fn delete_protected(
+    path: &Path,
+    len: u64,
+    mode: DeleteMode,
+    scan_id: &str,
+    settings: &ActionSettings,
+) -> Result<(), String> {
+    delete_with_progress(path, len, mode, scan_id, true, settings, &mut |_| {})
+}
+
+/// Like `delete`, calling `progress` now and then while removing a directory
+/// tree for good. With `allow_protected` it's like `delete_protected`.
+pub fn delete_with_progress(
+    path: &Path,
+    len: u64,
+    mode: DeleteMode,
+    scan_id: &str,
+    allow_protected: bool,
+    settings: &ActionSettings,
+    progress: &mut dyn FnMut(&RemoveProgress),
+) -> Result<(), String> {
+    if !allow_protected && settings.protected.is_protected(path) {
+        return Err(format!("{} is protected", path.display()));
+    }
+    // Even once the path itself was confirmed, nothing protected goes with it
+    if let Some(inside) = settings.protected.protected_inside(path) {
+        return Err(format!("{} holds the protected {}", path.display(), inside.display()));
+    }
+
+    let result = match mode {
+        _ if settings.dry_run => check_exists(path),
+        DeleteMode::Trash => trash::delete(path).map_err(|e| e.to_string()),
+        DeleteMode::Force => remove(path, settings, progress),
+    };
+
+    let entry = AuditEntry::new(path, len, action(mode), scan_id, &result, settings.dry_run);
+    if let Err(e) = record(&entry) {
+        let outcome = match &result {
+            Ok(_) => String::from("Deleted"),
+            Err(error) => error.clone(),
+        };
+        return Err(format!("{}, but {}", outcome, e));
+    }
+
+    result
+}
+
+fn action(mode: DeleteMode) -> AuditAction {
+    match mode {
+        DeleteMode::Trash => AuditAction::Trash,
+        DeleteMode::Force => AuditAction::Force,
+    }
+}
+
+/// What a dry run reports: deleting would fail if there's nothing at `path`
+fn check_exists(path: &Path) -> Result<(), String> {
+    path.symlink_metadata().map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Removes `path`, and everything under it when it's a directory. Trees
+/// holding a path protected in `settings` are refused, other filesystems
+/// mounted inside are left alone, and entries that can't be removed don't stop
+/// the rest from going. Dry runs are left to the callers.
+pub(crate) fn remove(
+    path: &Path,
+    settings: &ActionSettings,
+    progress: &mut dyn FnMut(&RemoveProgress),
+) -> Result<(), String> {
+    let meta = path.symlink_metadata().map_err(|e| e.to_string())?;
+    if !meta.is_dir() {
+        return fs::remove_file(path).map_err(|e| e.to_string());
+    }
+
+    if let Some(inside) = settings.protected.protected_inside(path) {
+        return Err(format!("{} holds the protected {}", path.display(), inside.display()));
+    }
+
+    let device = file_id(&meta).map(|(dev, _)| dev);
+    let parent_device = path
+        .parent()
+        .and_then(|parent| parent.symlink_metadata().ok())
+        .and_then(|meta| file_id(&meta))
+        .map(|(dev, _)| dev);
+    if device != parent_device {
+        return Err(format!("{} is a mount point", path.display()));
+    }
+
+    let mut removal = TreeRemoval {
+        device,
+        done: RemoveProgress::default(),
+        failures: vec![],
+        progress,
+    };
+    removal.remove_dir(path);
+    (removal.progress)(&removal.done);
+
+    match removal.failures.first() {
+        None => Ok(()),
+        Some((first, e)) => Err(format!(
+            "Removed {} files ({}), but {} entries couldn't be removed, like {}: {}",
+            removal.done.files,
+            bytes_to_human(removal.done.bytes, settings.units),
+            removal.failures.len(),
+            first.display(),
+            e
+        )),
+    }
+}
+
+/// How often removing a tree reports progress, in files
+const PROGRESS_FILES: u64 = 500;
+
+struct TreeRemoval<'a> {
+    device: Option<u64>,
+    done: RemoveProgress,
+    /// Entries that couldn't be removed and why. Directories left behind only
+    /// because something inside them failed aren't listed again.
+    failures: Vec<(PathBuf, String)>,
+    progress: &'a mut dyn FnMut(&RemoveProgress),
+}
+
+impl TreeRemoval<'_> {
+    /// Removes `dir` and what's in it, returning whether all of it went
+    fn remove_dir(&mut self, dir: &Path) -> bool {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.failures.push((dir.to_path_buf(), e.to_string()));
+                return false;
+            }
+        };
+
+        let mut emptied = true;
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.failures.push((dir.to_path_buf(), e.to_string()));
+                    emptied = false;
+                    continue;
+                }
+            };
+
+            emptied &= self.remove_entry(&entry.path());
+        }
+
+        if !emptied {
+            return false;
+        }
+
+        match fs::remove_dir(dir) {
+            Ok(_) => true,
+            Err(e) => {
+                self.failures.push((dir.to_path_buf(), e.to_string()));
+                false
+            }
+        }
+    }
+
+    // `u64::is_multiple_of` needs a newer Rust than the crate supports
+    #[allow(clippy::manual_is_multiple_of)]
+    fn remove_entry(&mut self, path: &Path) -> bool {
+        let meta = match path.symlink_metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                self.failures.push((path.to_path_buf(), e.to_string()));
+                return false;
+            }
+        };
+
+        if meta.is_dir() {
+            if file_id(&meta).map(|(dev, _)| dev) != self.device {
+                self.failures.push((path.to_path_buf(), String::from("another filesystem is mounted here")));
+                return false;
+            }
+            return self.remove_dir(path);
+        }
+
+        if let Err(e) = fs::remove_file(path) {
+            self.failures.push((path.to_path_buf(), e.to_string()));
+            return false;
+        }
+
+        self.done.files += 1;
+        self.done.bytes += meta.len();
+        if self.done.files % PROGRESS_FILES == 0 {
+            self.done.current = path.to_path_buf();
+            (self.progress)(&self.done);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{remove, ActionSettings, RemoveProgress};
+
+    #[test]
+    fn remove_tree_counts_files() {
+        let root = std::env::temp_dir().join(format!("disk-usage-remove-{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("a/one"), [0; 10]).unwrap();
+        fs::write(root.join("a/b/c/two"), [0; 20]).unwrap();
+
+        let mut last = RemoveProgress::default();
+        remove(&root, &ActionSettings::default(), &mut |progress| last = progress.clone()).unwrap();
+
+        assert!(!root.exists());
+        assert_eq!(last.files, 2);
+        assert_eq!(last.bytes, 30);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fmt, io, path::{Path, PathBuf}};
+
+/// The filesystem operation that failed during a scan
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScanOp {
+    ReadDir,
+    ReadEntry,
+    Metadata,
+    ReadIgnoreFile,
+}
+
+impl fmt::Display for ScanOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ScanOp::ReadDir => "read directory",
+            ScanOp::ReadEntry => "read directory entry",
+            ScanOp::Metadata => "read metadata",
+            ScanOp::ReadIgnoreFile => "parse ignore file",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An I/O error hit while walking, recorded so the walk can continue
+#[derive(Clone, Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub op: ScanOp,
+    pub kind: io::ErrorKind,
+    pub errno: Option<i32>,
+}
+
+impl ScanError {
+    pub fn new(path: &Path, op: ScanOp, err: &io::Error) -> ScanError {
+        ScanError {
+            path: path.to_path_buf(),
+            op,
+            kind: err.kind(),
+            errno: err.raw_os_error(),
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to {} {}: {}", self.op, self.path.display(), self.kind)?;
+        if let Some(errno) = self.errno {
+            write!(f, " (os error {})", errno)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a symlink couldn't be followed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymlinkIssue {
+    /// The target doesn't exist
+    Dangling,
+    /// The link can't be resolved, e.g. a chain of links that loops
+    Broken,
+    /// The target is a directory containing the link
+    Cycle,
+}
+
+/// A symlink found while walking that points nowhere useful
+#[derive(Clone, Debug)]
+pub struct BrokenSymlink {
+    pub path: PathBuf,
+    pub target: Option<PathBuf>,
+    pub issue: SymlinkIssue,
+}
+
+impl BrokenSymlink {
+    pub fn new(path: &Path, issue: SymlinkIssue) -> BrokenSymlink {
+        BrokenSymlink {
+            path: path.to_path_buf(),
+            target: std::fs::read_link(path).ok(),
+            issue,
+        }
+    }
+}
+
+impl fmt::Display for BrokenSymlink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let issue = match self.issue {
+            SymlinkIssue::Dangling => "dangling",
+            SymlinkIssue::Broken => "unresolvable",
+            SymlinkIssue::Cycle => "cycle",
+        };
+
+        match &self.target {
+            Some(target) => write!(f, "{} -> {} ({})", self.path.display(), target.display(), issue),
+            None => write!(f, "{} ({})", self.path.display(), issue),
+        }
+    }
+}
+
+/// Number of errors of one kind under a directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorSummary {
+    pub kind: io::ErrorKind,
+    pub dir: PathBuf,
+    pub count: u64,
+}
+
+impl fmt::Display for ErrorSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} under {}", self.count, self.kind, self.dir.display())
+    }
+}
+
+/// Groups errors by kind and the directory they happened in, most frequent first
+pub fn summarize_errors(errors: &[ScanError]) -> Vec<ErrorSummary> {
+    let mut groups: HashMap<(io::ErrorKind, PathBuf), u64> = HashMap::new();
+
+    for error in errors {
+        let dir = error.path
+            .parent()
+            .unwrap_or(&error.path)
+            .to_path_buf();
+
+        *groups.entry((error.kind, dir)).or_insert(0) += 1;
+    }
+
+    let mut summary: Vec<ErrorSummary> = groups
+        .into_iter()
+        .map(|((kind, dir), count)| ErrorSummary { kind, dir, count })
+        .collect();
+
+    summary.sort_by(|a, b| b.count.cmp(&a.count).then(a.dir.cmp(&b.dir)));
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::Path};
+
+    use super::{summarize_errors, ScanError, ScanOp};
+
+    #[test]
+    fn summarize_groups_by_kind_and_dir() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+
+        let errors = vec![
+            ScanError::new(Path::new("/var/lib/a"), ScanOp::ReadDir, &denied),
+            ScanError::new(Path::new("/var/lib/b"), ScanOp::ReadDir, &denied),
+            ScanError::new(Path::new("/var/lib/c"), ScanOp::Metadata, &missing),
+            ScanError::new(Path::new("/home/x"), ScanOp::ReadDir, &denied),
+        ];
+
+        let summary = summarize_errors(&errors);
+
+        assert_eq!(summary.len(), 3);
+        assert_eq!(summary[0].dir, Path::new("/var/lib"));
+        assert_eq!(summary[0].count, 2);
+        assert_eq!(summary[0].kind, io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A pattern that couldn't be parsed, or an exclude file that couldn't be read
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterError {
+    pub source: String,
+    pub pattern: String,
+    pub message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pattern.is_empty() {
+            write!(f, "{}: {}", self.source, self.message)
+        } else {
+            write!(f, "{}: invalid pattern \"{}\" ({})", self.source, self.pattern, self.message)
+        }
+    }
+}
+
+/// A single include (`!pattern`) or exclude rule, in gitignore syntax
+#[derive(Clone)]
+struct Rule {
+    label: String,
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    match_name: bool,
+    base: Option<PathBuf>,
+}
+
+impl Rule {
+    /// Parses one line of gitignore syntax. Rules with a `base` (from a `.gitignore`
+    /// found during the walk) are anchored to that directory. Rules without one come
+    /// from the settings or an exclude file and match absolute paths, so a pattern
+    /// like `build/out` matches at any depth while `/var/cache` only matches itself.
+    fn parse(line: &str, source: &str, base: Option<&Path>) -> Result<Option<Rule>, FilterError> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let mut text = line;
+
+        let negated = text.starts_with('!');
+        if negated {
+            text = &text[1..];
+        }
+
+        // "\#file" and "\!file" escape the leading character
+        if text.starts_with("\\#") || text.starts_with("\\!") {
+            text = &text[1..];
+        }
+
+        let dir_only = text.ends_with('/');
+        if dir_only {
+            text = text.trim_end_matches('/');
+        }
+
+        let match_name = !text.contains('/');
+
+        let glob = match base {
+            Some(_) => text.trim_start_matches('/').to_string(),
+            None if match_name || text.starts_with('/') || text.starts_with("**") => text.to_string(),
+            None => format!("**/{}", text),
+        };
+
+        let pattern = Pattern::new(&glob).map_err(|e| FilterError {
+            source: String::from(source),
+            pattern: String::from(line),
+            message: String::from(e.msg),
+        })?;
+
+        let label = match base {
+            Some(_) => format!("{} ({})", line, source),
+            None => String::from(line),
+        };
+
+        Ok(Some(Rule {
+            label,
+            pattern,
+            negated,
+            dir_only,
+            match_name,
+            base: base.map(|b| b.to_path_buf()),
+        }))
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.match_name {
+            return match path.file_name() {
+                Some(name) => self.pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS),
+                None => false,
+            };
+        }
+
+        match &self.base {
+            Some(base) => match path.strip_prefix(base) {
+                Ok(relative) => self.pattern.matches_with(&relative.to_string_lossy(), MATCH_OPTIONS),
+                Err(_) => false,
+            },
+            None => self.pattern.matches_with(&path.to_string_lossy(), MATCH_OPTIONS),
+        }
+    }
+}
+
+fn parse_rules(text: &str, source: &str, base: Option<&Path>) -> Result<Vec<Rule>, FilterError> {
+    let mut rules = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line_source = format!("{}:{}", source, i + 1);
+        if let Some(rule) = Rule::parse(line, &line_source, base)? {
+            rules.push(rule);
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Decides which paths a scan skips. Later rules override earlier ones, and rules
+/// from `.gitignore` files deeper in the tree override everything above them.
+pub struct Filter {
+    rules: Vec<Rule>,
+    use_gitignore: bool,
+    nested: RefCell<Vec<(PathBuf, Vec<Rule>)>>,
+    counts: RefCell<HashMap<String, u64>>,
+}
+
+impl Filter {
+    pub fn new(patterns: &[String], exclude_files: &[PathBuf], use_gitignore: bool) -> Result<Filter, FilterError> {
+        let mut rules = vec![];
+
+        for pattern in patterns {
+            if let Some(rule) = Rule::parse(pattern, "settings", None)? {
+                rules.push(rule);
+            }
+        }
+
+        for file in exclude_files {
+            let source = file.display().to_string();
+            let text = fs::read_to_string(file).map_err(|e| FilterError {
+                source: source.clone(),
+                pattern: String::from(""),
+                message: e.to_string(),
+            })?;
+
+            rules.extend(parse_rules(&text, &source, None)?);
+        }
+
+        Ok(Filter {
+            rules,
+            use_gitignore,
+            nested: RefCell::new(vec![]),
+            counts: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Loads `dir/.gitignore` when honoring gitignore files. Must be paired with `leave_dir`.
+    pub fn enter_dir(&self, dir: &Path) -> Result<(), FilterError> {
+        if !self.use_gitignore {
+            return Ok(());
+        }
+
+        let file = dir.join(".gitignore");
+        let rules = match fs::read_to_string(&file) {
+            Ok(text) => parse_rules(&text, &file.display().to_string(), Some(dir)),
+            Err(_) => Ok(vec![]),
+        };
+
+        // Push even on error so enter/leave stay balanced
+        let (rules, result) = match rules {
+            Ok(rules) => (rules, Ok(())),
+            Err(e) => (vec![], Err(e)),
+        };
+        self.nested.borrow_mut().push((dir.to_path_buf(), rules));
+
+        result
+    }
+
+    pub fn leave_dir(&self, dir: &Path) {
+        if !self.use_gitignore {
+            return;
+        }
+
+        let mut nested = self.nested.borrow_mut();
+        if let Some((top, _)) = nested.last() {
+            if top == dir {
+                nested.pop();
+            }
+        }
+    }
+
+    /// A copy with the same rules, including the `.gitignore`s entered so far, and
+    /// no exclusion counts, for walking a subtree on another thread
+    pub fn fork(&self) -> Filter {
+        Filter {
+            rules: self.rules.clone(),
+            use_gitignore: self.use_gitignore,
+            nested: RefCell::new(self.nested.borrow().clone()),
+            counts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Adds the exclusions counted by a forked filter
+    pub fn merge_counts(&self, other: &Filter) {
+        let mut counts = self.counts.borrow_mut();
+        for (label, count) in other.counts.borrow().iter() {
+            *counts.entry(label.clone()).or_insert(0) += count;
+        }
+    }
+
+    /// Whether `path` should be skipped, counting the exclusion against the deciding rule
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let nested = self.nested.borrow();
+
+        let deciding = self
+            .rules
+            .iter()
+            .chain(nested.iter().flat_map(|(_, rules)| rules.iter()))
+            .rev()
+            .find(|rule| rule.matches(path, is_dir));
+
+        match deciding {
+            Some(rule) if !rule.negated => {
+                *self.counts.borrow_mut().entry(rule.label.clone()).or_insert(0) += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// How many entries each rule excluded, most first
+    pub fn exclusion_counts(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self
+            .counts
+            .borrow()
+            .iter()
+            .map(|(label, count)| (label.clone(), *count))
+            .collect();
+
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_rules, Filter};
+
+    #[test]
+    fn later_rules_override_earlier() {
+        let patterns = vec![String::from("*.log"), String::from("!keep.log"), String::from("**/node_modules")];
+        let filter = Filter::new(&patterns, &[], false).unwrap();
+
+        assert!(filter.is_excluded(Path::new("/srv/app/debug.log"), false));
+        assert!(!filter.is_excluded(Path::new("/srv/app/keep.log"), false));
+        assert!(filter.is_excluded(Path::new("/Users/max/git/project/node_modules"), true));
+        assert!(!filter.is_excluded(Path::new("/srv/app/main.rs"), false));
+
+        assert_eq!(filter.exclusion_counts(), vec![
+            (String::from("**/node_modules"), 1),
+            (String::from("*.log"), 1),
+        ]);
+    }
+
+    #[test]
+    fn gitignore_rules_are_anchored_to_their_dir() {
+        let rules = parse_rules("# build output\n/target\nout/\n", ".gitignore", Some(Path::new("/p"))).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].matches(Path::new("/p/target"), true));
+        assert!(!rules[0].matches(Path::new("/p/sub/target"), true));
+        assert!(rules[1].matches(Path::new("/p/sub/out"), true));
+        assert!(!rules[1].matches(Path::new("/p/sub/out"), false));
+    }
+
+    #[test]
+    fn invalid_pattern_is_an_error() {
+        let err = Filter::new(&[String::from("[abc")], &[], false).err().unwrap();
+
+        assert_eq!(err.source, "settings");
+        assert_eq!(err.pattern, "[abc");
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, path::{Path, PathBuf}};
+
+/// Why an entry is considered junk
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JunkKind {
+    DanglingSymlink,
+    /// A directory holding nothing but other empty directories
+    EmptyDir,
+    EmptyFile,
+    /// Editor swap files, backups and leftover temp or lock files
+    TempFile,
+}
+
+impl JunkKind {
+    pub const ALL: [JunkKind; 4] = [
+        JunkKind::DanglingSymlink,
+        JunkKind::EmptyDir,
+        JunkKind::EmptyFile,
+        JunkKind::TempFile,
+    ];
+}
+
+impl fmt::Display for JunkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            JunkKind::DanglingSymlink => "Dangling symlinks",
+            JunkKind::EmptyDir => "Empty directories",
+            JunkKind::EmptyFile => "Zero-byte files",
+            JunkKind::TempFile => "Temporary and backup files",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An entry that can most likely be removed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Junk {
+    pub path: PathBuf,
+    pub kind: JunkKind,
+    pub len: u64,
+}
+
+/// Whether the file name looks like a temp file, swap file or backup left behind
+/// by an editor or office suite
+pub fn is_temp_file(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => String::new(),
+    };
+
+    name.ends_with('~')
+        || (name.starts_with(".~lock.") && name.ends_with('#'))
+        || matches!(extension.as_str(), "tmp" | "temp" | "swp" | "swo" | "bak")
+}
+
+/// Totals of `junk` per kind, as (kind, entries, bytes), skipping kinds with no entries
+pub fn summarize_junk(junk: &[Junk]) -> Vec<(JunkKind, u64, u64)> {
+    JunkKind::ALL
+        .iter()
+        .map(|kind| {
+            let entries = junk.iter().filter(|j| j.kind == *kind);
+            let count = entries.clone().count() as u64;
+            let len = entries.map(|j| j.len).sum();
+            (*kind, count, len)
+        })
+        .filter(|(_, count, _)| *count > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::is_temp_file;
+
+    #[test]
+    fn temp_file_names() {
+        assert!(is_temp_file(Path::new("/home/max/notes.txt~")));
+        assert!(is_temp_file(Path::new("/home/max/.notes.txt.swp")));
+        assert!(is_temp_file(Path::new("/tmp/upload.TMP")));
+        assert!(is_temp_file(Path::new("/home/max/.~lock.report.odt#")));
+        assert!(!is_temp_file(Path::new("/home/max/project/Cargo.lock")));
+        assert!(!is_temp_file(Path::new("/home/max/template")));
+    }
+}
@@ -0,0 +1,38 @@
+//! Disk usage analysis, independent of any UI.
+//!
+//! ```no_run
+//! use disk_usage::{scan, ScanSettings};
+//!
+//! let settings = ScanSettings::builder()
+//!     .dir("/var")
+//!     .ignore("*.tmp")
+//!     .nlargest(10)
+//!     .build();
+//!
+//! let results = scan(settings, |event| println!("{:?}", event)).unwrap();
+//!
+//! for (path, len) in results.largest_files.iter() {
+//!     println!("{} {}", path.display(), len);
+//! }
+//! ```
+
+pub mod analyzer;
+pub mod audit;
+pub mod check;
+pub mod compress;
+pub mod delete;
+pub mod errors;
+pub mod filter;
+pub mod junk;
+pub mod plan;
+pub mod progress;
+pub mod protect;
+pub mod relocate;
+pub mod restore;
+pub mod rules;
+pub mod scan;
+pub mod stats;
+pub mod utils;
+
+pub use analyzer::{scan, Analyzer, FileTreeNode, ScanSettings, ScanSettingsBuilder, UsageMode};
+pub use scan::Scan;
@@ -1,11 +1,30 @@
-use crate::{app::App};
+use clap::Parser;
 
-mod analyzer;
-mod utils;
-mod stats;
+use crate::cli::Args;
+
+#[cfg(feature = "gui")]
 mod app;
+mod cli;
+mod config;
+#[cfg(feature = "gui")]
 mod pie_chart;
 
 fn main() {
-    App::new();
+    let mut args = Args::parse();
+
+    if let Some(command) = args.command.take() {
+        cli::run_command(command, &args);
+        return;
+    }
+
+    if !args.dirs.is_empty() {
+        cli::run(args);
+        return;
+    }
+
+    #[cfg(feature = "gui")]
+    app::App::new(args.config);
+
+    #[cfg(not(feature = "gui"))]
+    <Args as clap::CommandFactory>::command().print_help().expect("Unable to print help");
 }
@@ -0,0 +1,199 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::delete::{delete_with_progress, ActionSettings, DeleteMode, RemoveProgress};
+
+const HEADER: &str = "# disk-usage cleanup plan. Review the items below, then run it with\n# `disk-usage plan <file> --run` or import it in the app.\n\n";
+
+/// One path picked for deletion
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlanItem {
+    #[serde(with = "crate::utils::escaped_path")]
+    pub path: PathBuf,
+    /// Usage reported by the scan, which is what deleting it should reclaim
+    pub bytes: u64,
+    pub mode: DeleteMode,
+}
+
+/// Paths picked across the results views, reviewed and deleted as one batch
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CleanupPlan {
+    /// The scan the paths were picked from
+    pub scan_id: String,
+    pub items: Vec<PlanItem>,
+}
+
+/// How deleting one item of a plan went
+#[derive(Clone, Debug)]
+pub struct PlanResult {
+    pub item: PlanItem,
+    pub result: Result<(), String>,
+}
+
+#[derive(Debug)]
+pub enum PlanError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Io(path, e) => write!(f, "Unable to access plan {}: {}", path.display(), e),
+            PlanError::Parse(path, e) => write!(f, "Invalid plan {}: {}", path.display(), e),
+            PlanError::Serialize(e) => write!(f, "Unable to write plan: {}", e),
+        }
+    }
+}
+
+impl CleanupPlan {
+    pub fn new(scan_id: &str) -> CleanupPlan {
+        CleanupPlan {
+            scan_id: String::from(scan_id),
+            items: vec![],
+        }
+    }
+
+    /// Adds `path`, or changes how it's deleted if it's already in the plan
+    pub fn add(&mut self, path: &Path, bytes: u64, mode: DeleteMode) {
+        match self.items.iter_mut().find(|item| item.path == path) {
+            Some(item) => item.mode = mode,
+            None => self.items.push(PlanItem { path: path.to_path_buf(), bytes, mode }),
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.items.retain(|item| item.path != path);
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.items.iter().any(|item| item.path == path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The items that aren't inside another item. Deleting those deletes the rest.
+    pub fn outermost(&self) -> Vec<&PlanItem> {
+        self.items
+            .iter()
+            .filter(|item| !is_nested(&item.path, self.items.iter().map(|other| other.path.as_path())))
+            .collect()
+    }
+
+    /// Bytes the plan should reclaim. Items inside other items aren't counted twice.
+    pub fn total_bytes(&self) -> u64 {
+        self.outermost().iter().map(|item| item.bytes).sum()
+    }
+
+    /// Deletes every outermost item in order, calling `on_progress` while
+    /// removing large directory trees and `on_result` after each item. During
+    /// a dry run nothing is deleted, see `delete`.
+    pub fn execute<P, F>(&self, settings: &ActionSettings, mut on_progress: P, mut on_result: F) -> Vec<PlanResult>
+    where
+        P: FnMut(&RemoveProgress),
+        F: FnMut(&PlanResult),
+    {
+        self.outermost()
+            .into_iter()
+            .map(|item| {
+                let result = delete_with_progress(&item.path, item.bytes, item.mode, &self.scan_id, false, settings, &mut on_progress);
+                let result = PlanResult {
+                    item: item.clone(),
+                    result,
+                };
+                on_result(&result);
+                result
+            })
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<CleanupPlan, PlanError> {
+        let text = fs::read_to_string(path).map_err(|e| PlanError::Io(path.to_path_buf(), e))?;
+
+        toml::from_str(&text).map_err(|e| PlanError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Writes the plan as TOML, with a comment saying how to run it
+    pub fn save(&self, path: &Path) -> Result<(), PlanError> {
+        let text = toml::to_string_pretty(self).map_err(PlanError::Serialize)?;
+
+        fs::write(path, format!("{}{}", HEADER, text)).map_err(|e| PlanError::Io(path.to_path_buf(), e))
+    }
+}
+
+/// Bytes reclaimed by the items that were deleted, not counting items inside
+/// other deleted items twice
+pub fn reclaimed_bytes(results: &[PlanResult]) -> u64 {
+    let deleted: Vec<&PlanItem> = results.iter().filter(|r| r.result.is_ok()).map(|r| &r.item).collect();
+
+    deleted
+        .iter()
+        .filter(|item| !is_nested(&item.path, deleted.iter().map(|other| other.path.as_path())))
+        .map(|item| item.bytes)
+        .sum()
+}
+
+/// Whether `path` is inside one of `others`
+fn is_nested<'a>(path: &Path, mut others: impl Iterator<Item = &'a Path>) -> bool {
+    others.any(|other| other != path && path.starts_with(other))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{reclaimed_bytes, CleanupPlan, PlanResult};
+    use crate::delete::DeleteMode;
+
+    #[test]
+    fn plan_round_trip() {
+        let mut plan = CleanupPlan::new("scan-1");
+        plan.add(Path::new("/data/project/node_modules"), 300, DeleteMode::Trash);
+        plan.add(Path::new("/data/project/node_modules/.cache"), 100, DeleteMode::Trash);
+        plan.add(Path::new("/data/old.iso"), 700, DeleteMode::Trash);
+        plan.add(Path::new("/data/old.iso"), 700, DeleteMode::Force);
+
+        assert_eq!(plan.items.len(), 3);
+        assert_eq!(plan.outermost().len(), 2);
+        assert_eq!(plan.total_bytes(), 1000);
+
+        // A path that isn't valid UTF-8 still saves and loads
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            plan.add(&Path::new("/data").join(OsStr::from_bytes(b"caf\xe9.bin")), 10, DeleteMode::Trash);
+        }
+
+        let file = std::env::temp_dir().join(format!("disk-usage-plan-{}.toml", std::process::id()));
+        plan.save(&file).unwrap();
+        let loaded = CleanupPlan::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded, plan);
+        assert_eq!(loaded.items[2].mode, DeleteMode::Force);
+    }
+
+    #[test]
+    fn nested_results_reclaimed_once() {
+        let deleted = |path: &str, bytes| PlanResult {
+            item: super::PlanItem { path: PathBuf::from(path), bytes, mode: DeleteMode::Trash },
+            result: Ok(()),
+        };
+
+        let results = [
+            deleted("/data/project/node_modules/.cache", 100),
+            deleted("/data/project/node_modules", 300),
+            deleted("/data/old.iso", 700),
+        ];
+
+        assert_eq!(reclaimed_bytes(&results), 1000);
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use crate::utils::{bytes_to_human, disk_space, DiskSpace, SizeUnits};
+
+/// Default minimum time between two `ProgressEvent::Update`s
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a running scan reports to its subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent {
+    Started {
+        dirs: Vec<PathBuf>,
+        expected_bytes: Option<u64>,
+    },
+    /// Running totals, sent at most once per progress interval
+    Update {
+        current_file: PathBuf,
+        bytes: u64,
+        files: u64,
+    },
+    Finished,
+}
+
+/// A subscriber called on the scanning thread
+type Callback = Box<dyn FnMut(&ProgressEvent)>;
+
+/// Fans progress events out to any number of subscribers, throttling updates so
+/// the walk never waits on a consumer
+pub struct ProgressEmitter {
+    senders: RefCell<Vec<Sender<ProgressEvent>>>,
+    callbacks: RefCell<Vec<Callback>>,
+    interval: Cell<Duration>,
+    last_update: Cell<Option<Instant>>,
+}
+
+impl Default for ProgressEmitter {
+    fn default() -> Self {
+        ProgressEmitter::new()
+    }
+}
+
+impl ProgressEmitter {
+    pub fn new() -> ProgressEmitter {
+        ProgressEmitter {
+            senders: RefCell::new(vec![]),
+            callbacks: RefCell::new(vec![]),
+            interval: Cell::new(DEFAULT_PROGRESS_INTERVAL),
+            last_update: Cell::new(None),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<ProgressEvent> {
+        let (tx, rx) = channel();
+        self.add_listener(tx);
+        rx
+    }
+
+    pub fn add_listener(&self, tx: Sender<ProgressEvent>) {
+        self.senders.borrow_mut().push(tx);
+    }
+
+    pub fn add_callback(&self, callback: Callback) {
+        self.callbacks.borrow_mut().push(callback);
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval.set(interval);
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval.get()
+    }
+
+    /// Sends to every subscriber, forgetting the ones that hung up
+    pub fn emit(&self, event: ProgressEvent) {
+        for callback in self.callbacks.borrow_mut().iter_mut() {
+            callback(&event);
+        }
+
+        self.senders
+            .borrow_mut()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Emits an update unless one was sent less than an interval ago
+    pub fn update(&self, current_file: &Path, bytes: u64, files: u64) {
+        if self.senders.borrow().is_empty() && self.callbacks.borrow().is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_update.get() {
+            if now.duration_since(last) < self.interval.get() {
+                return;
+            }
+        }
+        self.last_update.set(Some(now));
+
+        self.emit(ProgressEvent::Update {
+            current_file: current_file.to_path_buf(),
+            bytes,
+            files,
+        });
+    }
+
+    /// Emits the final totals, which the throttle may have held back, then
+    /// `ProgressEvent::Finished`
+    pub fn finish(&self, current_file: &Path, bytes: u64, files: u64) {
+        self.emit(ProgressEvent::Update {
+            current_file: current_file.to_path_buf(),
+            bytes,
+            files,
+        });
+        self.emit(ProgressEvent::Finished);
+    }
+}
+
+/// Estimates how many bytes a scan of `dirs` will see, from the used space of the
+/// filesystems containing them. Each filesystem is only counted once. This is an
+/// upper bound when a root is a subdirectory of its mount point.
+pub fn expected_bytes(dirs: &[PathBuf]) -> Option<u64> {
+    let mut disks: Vec<DiskSpace> = vec![];
+
+    for dir in dirs {
+        if let Some(disk) = disk_space(dir) {
+            if !disks.iter().any(|d| d.mount_point == disk.mount_point) {
+                disks.push(disk);
+            }
+        }
+    }
+
+    if disks.is_empty() {
+        return None;
+    }
+
+    Some(disks.iter().map(|disk| disk.total.saturating_sub(disk.available)).sum())
+}
+
+/// A snapshot of how far along a scan is
+pub struct Progress {
+    pub bytes: u64,
+    pub files: u64,
+    pub expected_bytes: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Fraction of the expected bytes seen so far, if there is an estimate
+    pub fn fraction(&self) -> Option<f32> {
+        match self.expected_bytes {
+            Some(expected) if expected > 0 => Some((self.bytes as f64 / expected as f64).min(1.0) as f32),
+            _ => None,
+        }
+    }
+
+    pub fn files_per_sec(&self) -> f64 {
+        per_sec(self.files, self.elapsed)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        per_sec(self.bytes, self.elapsed)
+    }
+
+    /// Time left at the current throughput
+    pub fn eta(&self) -> Option<Duration> {
+        let expected = self.expected_bytes?;
+        let rate = self.bytes_per_sec();
+
+        if rate <= 0.0 {
+            return None;
+        }
+
+        let remaining = expected.saturating_sub(self.bytes);
+
+        Some(Duration::from_secs_f64(remaining as f64 / rate))
+    }
+
+    /// One line summary with sizes in `units`, e.g. "42% 1.2 GB of 2.9 GB, 3400 files/s, 80 MB/s, ETA 0:00:21"
+    pub fn summary(&self, units: SizeUnits) -> String {
+        let mut line = String::new();
+
+        if let Some(fraction) = self.fraction() {
+            line.push_str(&format!(
+                "{:.0}% {} of ~{}, ",
+                fraction * 100.0,
+                bytes_to_human(self.bytes, units),
+                bytes_to_human(self.expected_bytes.unwrap_or(0), units)
+            ));
+        } else {
+            line.push_str(&format!("{}, ", bytes_to_human(self.bytes, units)));
+        }
+
+        line.push_str(&format!(
+            "{:.0} files/s, {}/s",
+            self.files_per_sec(),
+            bytes_to_human(self.bytes_per_sec() as u64, units)
+        ));
+
+        if let Some(eta) = self.eta() {
+            line.push_str(&format!(", ETA {}", format_duration(eta)));
+        }
+
+        line
+    }
+}
+
+fn per_sec(value: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+
+    if secs <= 0.0 {
+        return 0.0;
+    }
+
+    value as f64 / secs
+}
+
+/// Formats a duration as h:mm:ss
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use super::{format_duration, Progress, ProgressEmitter, ProgressEvent};
+
+    #[test]
+    fn emitter_throttles_updates() {
+        let emitter = ProgressEmitter::new();
+        emitter.set_interval(Duration::from_secs(3600));
+        let rx = emitter.subscribe();
+
+        emitter.update(Path::new("/a"), 1, 1);
+        emitter.update(Path::new("/b"), 2, 2);
+        emitter.finish(Path::new("/c"), 3, 3);
+
+        let events: Vec<ProgressEvent> = rx.try_iter().collect();
+        assert_eq!(events, vec![
+            ProgressEvent::Update { current_file: Path::new("/a").to_path_buf(), bytes: 1, files: 1 },
+            ProgressEvent::Update { current_file: Path::new("/c").to_path_buf(), bytes: 3, files: 3 },
+            ProgressEvent::Finished,
+        ]);
+    }
+
+    #[test]
+    fn progress_eta() {
+        let progress = Progress {
+            bytes: 250,
+            files: 10,
+            expected_bytes: Some(1000),
+            elapsed: Duration::from_secs(5),
+        };
+
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert_eq!(progress.bytes_per_sec(), 50.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(15)));
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+/// Operating system directories, nothing in them should be deleted from here
+#[cfg(unix)]
+const SYSTEM_DIRS: &[&str] = &[
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/proc",
+    "/run",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/var/lib",
+    "/System",
+    "/Library",
+    "/private/etc",
+    "/private/var/db",
+];
+
+#[cfg(windows)]
+const SYSTEM_DIRS: &[&str] = &[
+    "C:\\Windows",
+    "C:\\Program Files",
+    "C:\\Program Files (x86)",
+    "C:\\ProgramData",
+];
+
+#[cfg(not(any(unix, windows)))]
+const SYSTEM_DIRS: &[&str] = &[];
+
+/// Directories the user asked to keep, on top of the system ones. Like the
+/// system directories, everything under them is protected too.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProtectedPaths {
+    paths: Vec<PathBuf>,
+}
+
+impl ProtectedPaths {
+    pub fn new(paths: &[PathBuf]) -> ProtectedPaths {
+        let mut protected = vec![];
+        for path in paths {
+            // Scanned paths are canonical, so match on the canonical path as well
+            if let Ok(canonical) = path.canonicalize() {
+                if &canonical != path {
+                    protected.push(canonical);
+                }
+            }
+            protected.push(path.clone());
+        }
+
+        ProtectedPaths { paths: protected }
+    }
+
+    /// Whether deleting `path` could break the system or lose something the
+    /// user asked to keep: a system directory, a protected path, anything in
+    /// one or any directory holding one, the filesystem root, or the home
+    /// directory. The path is normalized first, so `..` can't be used to step
+    /// around the check.
+    pub fn is_protected(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        let home = dirs::home_dir();
+
+        if path.parent().is_none() || home.as_deref() == Some(&*path) {
+            return true;
+        }
+
+        let guarded = |dir: &Path| path.starts_with(dir) || dir.starts_with(&path);
+
+        SYSTEM_DIRS.iter().any(|dir| guarded(Path::new(dir)))
+            || self.paths.iter().any(|dir| guarded(dir))
+            || home.as_deref().is_some_and(|home| home.starts_with(&path))
+    }
+
+    /// The first system directory, protected path or home directory strictly
+    /// inside `path`, which removing `path` as a tree would take with it.
+    /// Unlike `is_protected`, `path` itself being protected doesn't count, so
+    /// this still holds after the user confirmed deleting that particular path.
+    pub fn protected_inside(&self, path: &Path) -> Option<PathBuf> {
+        let path = normalize(path);
+
+        SYSTEM_DIRS
+            .iter()
+            .map(PathBuf::from)
+            .chain(self.paths.iter().cloned())
+            .chain(dirs::home_dir())
+            .find(|dir| dir != &path && dir.starts_with(&path))
+    }
+}
+
+/// `path` made absolute with `.` and `..` resolved. Symlinks in its parent are
+/// resolved when it exists, but not `path` itself, since a symlink is deleted
+/// as a link.
+fn normalize(path: &Path) -> PathBuf {
+    let resolved = match path.file_name() {
+        Some(name) => path
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .map(|parent| parent.join(name)),
+        None => path.canonicalize().ok(),
+    };
+    if let Some(resolved) = resolved {
+        return resolved;
+    }
+
+    let absolute = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf()),
+    };
+
+    let mut normal = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::ProtectedPaths;
+
+    #[cfg(unix)]
+    #[test]
+    fn system_and_configured_paths() {
+        let protected = ProtectedPaths::new(&[PathBuf::from("/srv/keep")]);
+        let is_protected = |path: &Path| protected.is_protected(path);
+
+        assert!(is_protected(Path::new("/")));
+        assert!(is_protected(Path::new("/etc/passwd")));
+        assert!(is_protected(Path::new("/usr/lib/libc.so.6")));
+        assert!(is_protected(Path::new("/srv/keep/db.sqlite")));
+        assert!(!is_protected(Path::new("/srv/keeper")));
+        assert!(!is_protected(Path::new("/var/cache/apt/archives/old.deb")));
+        assert!(!is_protected(Path::new("/tmp/build.log")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parents_and_dot_dot_paths() {
+        let is_protected = |path: &Path| ProtectedPaths::default().is_protected(path);
+
+        // /var holds /var/lib
+        assert!(is_protected(Path::new("/var")));
+        assert!(is_protected(Path::new("/var/")));
+        assert!(!is_protected(Path::new("/var/cache")));
+
+        assert!(is_protected(Path::new("/tmp/../etc/passwd")));
+        assert!(is_protected(Path::new("/tmp/../var")));
+        assert!(is_protected(Path::new("/nonexistent/../../usr/lib")));
+        assert!(!is_protected(Path::new("/tmp/./build/../build.log")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn protected_paths_inside_trees() {
+        let protected_inside = |path: &Path| ProtectedPaths::default().protected_inside(path);
+
+        assert_eq!(protected_inside(Path::new("/var")), Some(PathBuf::from("/var/lib")));
+        assert_eq!(protected_inside(Path::new("/tmp/../var")), Some(PathBuf::from("/var/lib")));
+        assert_eq!(protected_inside(Path::new("/var/lib")), None);
+        assert_eq!(protected_inside(Path::new("/var/cache")), None);
+        assert!(protected_inside(Path::new("/")).is_some());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use filetime::FileTime;
+
+use crate::{
+    audit::{record, AuditAction, AuditEntry},
+    compress::same_contents,
+    delete::{remove, ActionSettings},
+    protect::ProtectedPaths,
+    utils::{bytes_to_human, device_id, disk_space, file_id},
+};
+
+/// Size of the chunks files are copied in, and how often copying reports progress
+const CHUNK: usize = 1024 * 1024;
+
+/// What moving a path somewhere else did
+#[derive(Clone, Debug)]
+pub struct Relocated {
+    pub path: PathBuf,
+    /// Where it is now
+    pub destination: PathBuf,
+    /// Bytes in the files moved
+    pub bytes: u64,
+    /// Whether a symlink to the destination was left at `path`
+    pub linked: bool,
+}
+
+/// Free space on the filesystem `dir` lives on, if it can be told
+pub fn free_space(dir: &Path) -> Option<u64> {
+    disk_space(dir).map(|disk| disk.available)
+}
+
+/// Moves the file or directory at `path` into the directory `dest_dir`, leaving
+/// a symlink to its new place behind when `leave_symlink` is set. The outcome
+/// is recorded in the audit log.
+///
+/// Within one filesystem it's renamed. Across filesystems it's copied, each
+/// file is checked against the original, and only then is the original
+/// removed. `progress` gets the bytes copied so far. Moves that wouldn't fit
+/// in the free space on the target are refused before anything is copied.
+///
+/// During a dry run only those checks are made.
+pub fn relocate(
+    path: &Path,
+    dest_dir: &Path,
+    leave_symlink: bool,
+    scan_id: &str,
+    settings: &ActionSettings,
+    progress: &mut dyn FnMut(u64),
+) -> Result<Relocated, String> {
+    let result = check(path, dest_dir, &settings.protected).and_then(|(destination, bytes)| {
+        let same_filesystem = device_id(dest_dir).is_some() && device_id(dest_dir) == parent_device(path);
+
+        if !same_filesystem {
+            if let Some(free) = free_space(dest_dir) {
+                if free < bytes {
+                    return Err(format!(
+                        "Only {} free on {}, {} is needed",
+                        bytes_to_human(free, settings.units),
+                        dest_dir.display(),
+                        bytes_to_human(bytes, settings.units)
+                    ));
+                }
+            }
+        }
+
+        if !settings.dry_run {
+            match same_filesystem {
+                true => fs::rename(path, &destination).map_err(|e| e.to_string())?,
+                false => move_across(path, &destination, settings, progress)?,
+            }
+
+            if leave_symlink {
+                symlink(&destination, path).map_err(|e| {
+                    format!("Moved to {}, but couldn't leave a symlink behind: {}", destination.display(), e)
+                })?;
+            }
+        }
+
+        Ok(Relocated {
+            path: path.to_path_buf(),
+            destination,
+            bytes,
+            linked: leave_symlink,
+        })
+    });
+
+    let len = result.as_ref().map(|relocated| relocated.bytes).unwrap_or(0);
+    let outcome = result.as_ref().map(|_| ()).map_err(|e| e.clone());
+    if let Err(e) = record(&AuditEntry::new(path, len, AuditAction::Relocate, scan_id, &outcome, settings.dry_run)) {
+        let outcome = match &result {
+            Ok(relocated) => format!("Moved to {}", relocated.destination.display()),
+            Err(error) => error.clone(),
+        };
+        return Err(format!("{}, but {}", outcome, e));
+    }
+
+    result
+}
+
+/// Where `path` ends up in `dest_dir`, and the bytes in it
+fn check(path: &Path, dest_dir: &Path, protected: &ProtectedPaths) -> Result<(PathBuf, u64), String> {
+    if protected.is_protected(path) {
+        return Err(format!("{} is protected", path.display()));
+    }
+
+    let meta = path.symlink_metadata().map_err(|e| e.to_string())?;
+    let name = path.file_name().ok_or_else(|| format!("{} has no name to move it under", path.display()))?;
+
+    if !dest_dir.is_dir() {
+        return Err(format!("{} is not a directory", dest_dir.display()));
+    }
+    // Only the parent is resolved, `path` itself may be a symlink that's moved as one
+    let from = path.parent().and_then(|parent| parent.canonicalize().ok()).map(|parent| parent.join(name));
+    if let (Some(from), Ok(to)) = (from, dest_dir.canonicalize()) {
+        if to.starts_with(&from) {
+            return Err(format!("{} can't be moved into itself", path.display()));
+        }
+    }
+
+    let destination = dest_dir.join(name);
+    if destination.symlink_metadata().is_ok() {
+        return Err(format!("{} already exists", destination.display()));
+    }
+
+    let bytes = match meta.is_dir() {
+        true => tree_bytes(path).map_err(|e| e.to_string())?,
+        false => meta.len(),
+    };
+
+    Ok((destination, bytes))
+}
+
+fn parent_device(path: &Path) -> Option<u64> {
+    path.parent().and_then(device_id)
+}
+
+/// Bytes in the files under `dir`, without following symlinks
+fn tree_bytes(dir: &Path) -> io::Result<u64> {
+    let mut bytes = 0;
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let meta = path.symlink_metadata()?;
+
+            match meta.is_dir() {
+                true => pending.push(path),
+                false if meta.is_file() => bytes += meta.len(),
+                false => {}
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Copies `path` to `destination`, checking every file, then removes `path`.
+/// When anything goes wrong, what the copy created is removed again, and
+/// nothing else.
+fn move_across(
+    path: &Path,
+    destination: &Path,
+    settings: &ActionSettings,
+    progress: &mut dyn FnMut(u64),
+) -> Result<(), String> {
+    let device = path.symlink_metadata().ok().as_ref().and_then(file_id).map(|(dev, _)| dev);
+    let mut copy = TreeCopy {
+        device,
+        copied: 0,
+        created: vec![],
+        progress,
+    };
+
+    if let Err(e) = copy.copy(path, destination) {
+        copy.roll_back();
+        return Err(format!("Unable to copy to {}: {}", destination.display(), e));
+    }
+
+    remove(path, settings, &mut |_| {}).map_err(|e| format!("Copied to {}, but {}", destination.display(), e))
+}
+
+struct TreeCopy<'a> {
+    /// The device being copied from, so other filesystems mounted inside aren't
+    device: Option<u64>,
+    copied: u64,
+    /// Everything this copy created, parents before what's in them
+    created: Vec<PathBuf>,
+    progress: &'a mut dyn FnMut(u64),
+}
+
+impl TreeCopy<'_> {
+    fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let meta = from.symlink_metadata()?;
+
+        if meta.file_type().is_symlink() {
+            symlink(&fs::read_link(from)?, to)?;
+            self.created.push(to.to_path_buf());
+            return Ok(());
+        }
+
+        if meta.is_dir() {
+            if file_id(&meta).map(|(dev, _)| dev) != self.device {
+                return Err(io::Error::other(format!("another filesystem is mounted at {}", from.display())));
+            }
+
+            fs::create_dir(to)?;
+            self.created.push(to.to_path_buf());
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                self.copy(&entry.path(), &to.join(entry.file_name()))?;
+            }
+        } else if meta.is_file() {
+            self.copy_file(from, to)?;
+        } else {
+            return Err(io::Error::other(format!("{} isn't a file, directory or symlink", from.display())));
+        }
+
+        // After the contents, so a read-only directory can still be filled
+        fs::set_permissions(to, meta.permissions())?;
+        filetime::set_file_mtime(to, FileTime::from_last_modification_time(&meta))
+    }
+
+    fn copy_file(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut input = File::open(from)?;
+        let mut output = OpenOptions::new().write(true).create_new(true).open(to)?;
+        self.created.push(to.to_path_buf());
+        let mut buf = vec![0; CHUNK];
+
+        loop {
+            let read = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            output.write_all(&buf[..read])?;
+
+            self.copied += read as u64;
+            (self.progress)(self.copied);
+        }
+        output.sync_all()?;
+
+        if !same_contents(&mut File::open(from)?, &mut File::open(to)?)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the copy of {} doesn't match", from.display()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Removes what the copy created, innermost first. Directories are only
+    /// removed once empty, so anything else put in them stays.
+    fn roll_back(&mut self) {
+        for path in self.created.drain(..).rev() {
+            let _ = match path.symlink_metadata() {
+                Ok(meta) if meta.is_dir() => fs::remove_dir(&path),
+                _ => fs::remove_file(&path),
+            };
+        }
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    match target.is_dir() {
+        true => std::os::windows::fs::symlink_dir(target, link),
+        false => std::os::windows::fs::symlink_file(target, link),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::other("symlinks aren't supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::move_across;
+    use crate::delete::ActionSettings;
+
+    #[test]
+    fn copy_then_remove() {
+        let root = std::env::temp_dir().join(format!("disk-usage-relocate-{}", std::process::id()));
+        fs::create_dir_all(root.join("media/season 1")).unwrap();
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::write(root.join("media/season 1/episode.mkv"), vec![7; 3 * 1024 * 1024 + 5]).unwrap();
+        fs::write(root.join("media/cover.jpg"), [1; 100]).unwrap();
+
+        let mut copied = 0;
+        let settings = ActionSettings::default();
+        move_across(&root.join("media"), &root.join("archive/media"), &settings, &mut |bytes| copied = bytes).unwrap();
+
+        assert!(!root.join("media").exists());
+        assert_eq!(copied, 3 * 1024 * 1024 + 105);
+        assert_eq!(fs::read(root.join("archive/media/cover.jpg")).unwrap(), [1; 100]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_copy_leaves_existing_destination() {
+        let root = std::env::temp_dir().join(format!("disk-usage-relocate-existing-{}", std::process::id()));
+        fs::create_dir_all(root.join("media")).unwrap();
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::write(root.join("media/cover.jpg"), [1; 100]).unwrap();
+        // Appeared after the destination was checked
+        fs::write(root.join("archive/media"), [2; 10]).unwrap();
+
+        let settings = ActionSettings::default();
+        assert!(move_across(&root.join("media"), &root.join("archive/media"), &settings, &mut |_| {}).is_err());
+
+        assert_eq!(fs::read(root.join("archive/media")).unwrap(), [2; 10]);
+        assert_eq!(fs::read(root.join("media/cover.jpg")).unwrap(), [1; 100]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, TimeZone};
+use trash::TrashItem;
+
+use crate::{
+    audit::{record, AuditAction, AuditEntry, AuditLog},
+};
+
+/// How far apart, in seconds, the trash's deletion time and the audit log's
+/// timestamp can be for them to describe the same deletion
+const MATCH_WINDOW_SECS: i64 = 5;
+
+/// An item in the trash that this app put there, found through the audit log
+#[derive(Debug)]
+pub struct TrashedItem {
+    pub path: PathBuf,
+    pub len: u64,
+    pub deleted_at: DateTime<Local>,
+    item: TrashItem,
+}
+
+impl Clone for TrashedItem {
+    fn clone(&self) -> Self {
+        TrashedItem {
+            path: self.path.clone(),
+            len: self.len,
+            deleted_at: self.deleted_at,
+            item: TrashItem {
+                id: self.item.id.clone(),
+                name: self.item.name.clone(),
+                original_parent: self.item.original_parent.clone(),
+                time_deleted: self.item.time_deleted,
+            },
+        }
+    }
+}
+
+/// Everything this app moved to the trash that's still there, most recent first
+pub fn trashed_items() -> Result<Vec<TrashedItem>, String> {
+    let entries = match AuditLog::default_location() {
+        Some(log) => log.read().map_err(|e| format!("Unable to read audit log: {}", e))?,
+        None => vec![],
+    };
+
+    let mut items: Vec<TrashedItem> = bin::list().map_err(|e| format!("Unable to list the trash: {}", e))?
+        .into_iter()
+        .filter_map(|item| {
+            let path = item.original_path();
+            let entry = entries.iter().rev().find(|entry| {
+                entry.action == AuditAction::Trash
+                    && entry.error.is_none()
+                    && !entry.dry_run
+                    && entry.path == path
+                    && (entry.timestamp.timestamp() - item.time_deleted).abs() <= MATCH_WINDOW_SECS
+            })?;
+
+            Some(TrashedItem {
+                path,
+                len: entry.len,
+                deleted_at: Local.timestamp_opt(item.time_deleted, 0).single()?,
+                item,
+            })
+        })
+        .collect();
+
+    items.sort_by_key(|item| Reverse(item.deleted_at));
+
+    Ok(items)
+}
+
+/// Moves `item` back to where it was deleted from. With `dry_run` it's left
+/// in the trash and only the audit log says it would have been restored.
+pub fn restore(item: TrashedItem, scan_id: &str, dry_run: bool) -> Result<(), String> {
+    let (path, len) = (item.path.clone(), item.len);
+    let result = match dry_run {
+        true => Ok(()),
+        false => bin::restore_all(vec![item.item]).map_err(|e| e.to_string()),
+    };
+
+    log(&path, len, AuditAction::Restore, scan_id, dry_run, result)
+}
+
+/// Removes `item` from the trash for good, or with `dry_run` only records
+/// that it would have been
+pub fn purge(item: TrashedItem, scan_id: &str, dry_run: bool) -> Result<(), String> {
+    let (path, len) = (item.path.clone(), item.len);
+    let result = match dry_run {
+        true => Ok(()),
+        false => bin::purge_all(vec![item.item]).map_err(|e| e.to_string()),
+    };
+
+    log(&path, len, AuditAction::Purge, scan_id, dry_run, result)
+}
+
+/// Records the outcome of `action` in the audit log, like a deletion
+fn log(path: &Path, len: u64, action: AuditAction, scan_id: &str, dry_run: bool, result: Result<(), String>) -> Result<(), String> {
+    if let Err(e) = record(&AuditEntry::new(path, len, action, scan_id, &result, dry_run)) {
+        let outcome = match &result {
+            Ok(_) => String::from("Done"),
+            Err(error) => error.clone(),
+        };
+        return Err(format!("{}, but {}", outcome, e));
+    }
+
+    result
+}
+
+/// Restores the most recent trash deletion of each of `paths` made by this app
+pub fn undo(paths: &[PathBuf], scan_id: &str, dry_run: bool) -> Result<(), String> {
+    let items = trashed_items()?;
+
+    for path in paths {
+        // Newest first, so this is the latest deletion of `path`
+        if let Some(item) = items.iter().find(|item| &item.path == path) {
+            restore(item.clone(), scan_id, dry_run)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The trash crate can only list and restore items on Windows and freedesktop systems
+#[cfg(any(
+    target_os = "windows",
+    all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))
+))]
+mod bin {
+    pub use trash::os_limited::{list, purge_all, restore_all};
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))
+)))]
+mod bin {
+    use trash::{Error, TrashItem};
+
+    fn unsupported() -> Error {
+        Error::Unknown {
+            description: String::from("the trash can't be listed on this platform"),
+        }
+    }
+
+    pub fn list() -> Result<Vec<TrashItem>, Error> {
+        Err(unsupported())
+    }
+
+    pub fn purge_all(_items: Vec<TrashItem>) -> Result<(), Error> {
+        Err(unsupported())
+    }
+
+    pub fn restore_all(_items: Vec<TrashItem>) -> Result<(), Error> {
+        Err(unsupported())
+    }
+}
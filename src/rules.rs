@@ -0,0 +1,377 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analyzer::FileTreeNode,
+    delete::DeleteMode,
+    filter::{Filter, FilterError},
+    plan::CleanupPlan,
+    protect::ProtectedPaths,
+    utils::is_hidden,
+    Scan, ScanSettings,
+};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// `*` and `?` stay within one path component, only `**` crosses into subdirectories
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// What a rule's pattern is matched against
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleTarget {
+    #[default]
+    File,
+    Dir,
+}
+
+fn default_mode() -> DeleteMode {
+    DeleteMode::Trash
+}
+
+/// Picks paths for deletion, like "*.log older than 30 days under /var/app/logs"
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// Shown when reporting what the rule picked
+    #[serde(default)]
+    pub name: String,
+    /// Glob the full path has to match, like /var/app/logs/**/*.log or **/node_modules
+    pub pattern: String,
+    #[serde(default)]
+    pub target: RuleTarget,
+    /// Only paths that haven't been modified for this many days. A directory
+    /// counts as modified when anything in it was.
+    #[serde(default)]
+    pub older_than_days: u64,
+    #[serde(default = "default_mode")]
+    pub mode: DeleteMode,
+}
+
+impl Rule {
+    /// The rule's name, or its pattern when it has none
+    pub fn label(&self) -> &str {
+        match self.name.is_empty() {
+            true => &self.pattern,
+            false => &self.name,
+        }
+    }
+}
+
+/// A rules file: the directories to scan and the rules to evaluate against the scan
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct RuleSet {
+    /// Directories to scan, instead of the configured ones when not empty
+    pub dirs: Vec<PathBuf>,
+    #[serde(rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+/// A path a rule picked
+#[derive(Clone, Debug)]
+pub struct RuleMatch {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// Index of the rule in the rule set
+    pub rule: usize,
+}
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Pattern(String, glob::PatternError),
+    Filter(FilterError),
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(path, e) => write!(f, "Unable to read rules {}: {}", path.display(), e),
+            RulesError::Parse(path, e) => write!(f, "Invalid rules {}: {}", path.display(), e),
+            RulesError::Pattern(pattern, e) => write!(f, "Invalid rule pattern \"{}\": {}", pattern, e),
+            RulesError::Filter(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl RuleSet {
+    /// Reads a rules file, checking every pattern is a valid glob
+    pub fn load(path: &Path) -> Result<RuleSet, RulesError> {
+        let text = fs::read_to_string(path).map_err(|e| RulesError::Io(path.to_path_buf(), e))?;
+        let rules: RuleSet = toml::from_str(&text).map_err(|e| RulesError::Parse(path.to_path_buf(), e))?;
+        rules.patterns()?;
+
+        Ok(rules)
+    }
+
+    fn patterns(&self) -> Result<Vec<Pattern>, RulesError> {
+        self.rules
+            .iter()
+            .map(|rule| Pattern::new(&rule.pattern).map_err(|e| RulesError::Pattern(rule.pattern.clone(), e)))
+            .collect()
+    }
+
+    /// The paths in `scan` the rules pick, each with the first rule matching
+    /// it. Directories come from the scan's tree and files are looked up in
+    /// them, skipping the ones `settings` kept out of the scan. Nothing inside
+    /// a picked directory is picked again, and `protected` paths are never picked.
+    pub fn evaluate(
+        &self,
+        scan: &Scan,
+        settings: &ScanSettings,
+        protected: &ProtectedPaths,
+    ) -> Result<Vec<RuleMatch>, RulesError> {
+        let evaluation = Evaluation {
+            rules: &self.rules,
+            protected,
+            patterns: self.patterns()?,
+            filter: Filter::new(&settings.ignore, &settings.exclude_files, settings.use_gitignore)
+                .map_err(RulesError::Filter)?,
+            hidden: settings.hidden,
+            now: SystemTime::now(),
+        };
+
+        let mut matches = vec![];
+        for node in scan.tree.iter() {
+            evaluation.visit(node, &mut matches);
+        }
+
+        Ok(matches)
+    }
+
+    /// A cleanup plan deleting `matches` from the scan `scan_id`, each the way its rule says
+    pub fn plan(&self, scan_id: &str, matches: &[RuleMatch]) -> CleanupPlan {
+        let mut plan = CleanupPlan::new(scan_id);
+        for m in matches {
+            plan.add(&m.path, m.bytes, self.rules[m.rule].mode);
+        }
+
+        plan
+    }
+}
+
+struct Evaluation<'a> {
+    rules: &'a [Rule],
+    protected: &'a ProtectedPaths,
+    patterns: Vec<Pattern>,
+    /// The scan's filter, so files it skipped aren't picked either
+    filter: Filter,
+    hidden: bool,
+    now: SystemTime,
+}
+
+impl Evaluation<'_> {
+    fn visit(&self, node: &FileTreeNode, matches: &mut Vec<RuleMatch>) {
+        // The scan already reported a .gitignore it couldn't read
+        let _ = self.filter.enter_dir(&node.path);
+
+        // Roots are never picked, only what's in them
+        self.visit_files(&node.path, matches);
+
+        for child in node.children.iter() {
+            match self.dir_match(&child.path) {
+                Some(m) => matches.push(m),
+                None => self.visit(child, matches),
+            }
+        }
+
+        self.filter.leave_dir(&node.path);
+    }
+
+    fn visit_files(&self, dir: &Path, matches: &mut Vec<RuleMatch>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !self.hidden && is_hidden(&path) {
+                continue;
+            }
+
+            let meta = match path.symlink_metadata() {
+                Ok(meta) if meta.is_file() => meta,
+                _ => continue,
+            };
+            if self.filter.is_excluded(&path, false) {
+                continue;
+            }
+
+            let modified = meta.modified().ok();
+            if let Some(rule) = self.matching_rule(&path, RuleTarget::File, modified) {
+                matches.push(RuleMatch {
+                    path,
+                    bytes: meta.len(),
+                    rule,
+                });
+            }
+        }
+    }
+
+    fn dir_match(&self, dir: &Path) -> Option<RuleMatch> {
+        // Only look inside a directory when a rule could pick it
+        let candidates = self.candidates(dir, RuleTarget::Dir);
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let (modified, bytes) = newest_and_bytes(dir)?;
+        let rule = candidates.into_iter().find(|&i| self.old_enough(i, Some(modified)))?;
+
+        Some(RuleMatch {
+            path: dir.to_path_buf(),
+            bytes,
+            rule,
+        })
+    }
+
+    fn matching_rule(&self, path: &Path, target: RuleTarget, modified: Option<SystemTime>) -> Option<usize> {
+        self.candidates(path, target)
+            .into_iter()
+            .find(|&i| self.old_enough(i, modified))
+    }
+
+    /// Rules for `target` whose pattern matches `path`, unless it's protected
+    fn candidates(&self, path: &Path, target: RuleTarget) -> Vec<usize> {
+        if self.protected.is_protected(path) {
+            return vec![];
+        }
+
+        (0..self.rules.len())
+            .filter(|&i| self.rules[i].target == target && self.patterns[i].matches_path_with(path, MATCH_OPTIONS))
+            .collect()
+    }
+
+    fn old_enough(&self, rule: usize, modified: Option<SystemTime>) -> bool {
+        let min_age = Duration::from_secs(self.rules[rule].older_than_days * SECS_PER_DAY);
+        if min_age.is_zero() {
+            return true;
+        }
+
+        // Without a modification time, or with one in the future, the age is unknown
+        modified
+            .and_then(|modified| self.now.duration_since(modified).ok())
+            .is_some_and(|age| age >= min_age)
+    }
+}
+
+/// The most recent modification of `dir` or anything in it, and the bytes in
+/// its files. Symlinks aren't followed. `None` when any of it can't be read,
+/// since then how recently it was modified isn't known.
+fn newest_and_bytes(dir: &Path) -> Option<(SystemTime, u64)> {
+    let mut newest = dir.symlink_metadata().ok()?.modified().ok()?;
+    let mut bytes = 0;
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).ok()? {
+            let path = entry.ok()?.path();
+            let meta = path.symlink_metadata().ok()?;
+
+            newest = newest.max(meta.modified().ok()?);
+            if meta.is_dir() {
+                pending.push(path);
+            } else if meta.is_file() {
+                bytes += meta.len();
+            }
+        }
+    }
+
+    Some((newest, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use filetime::FileTime;
+
+    use super::RuleSet;
+    use crate::{delete::DeleteMode, protect::ProtectedPaths, scan, ScanSettings};
+
+    #[test]
+    fn rules_pick_old_matches() {
+        let root = std::env::temp_dir().join(format!("disk-usage-rules-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("logs/2021")).unwrap();
+        fs::create_dir_all(root.join("old-project/node_modules/left-pad/node_modules")).unwrap();
+        fs::create_dir_all(root.join("new-project/node_modules")).unwrap();
+        fs::write(root.join("logs/2021/app.log"), [0; 10]).unwrap();
+        fs::write(root.join("logs/app.log"), [0; 10]).unwrap();
+        fs::write(root.join("logs/app.txt"), [0; 10]).unwrap();
+        fs::write(root.join("logs/2021/.app.log"), [0; 10]).unwrap();
+        fs::write(root.join("logs/2021/app.log.keep"), [0; 10]).unwrap();
+        fs::write(root.join("old-project/node_modules/left-pad/index.js"), [0; 20]).unwrap();
+        fs::write(root.join("new-project/node_modules/index.js"), [0; 20]).unwrap();
+
+        let old = FileTime::from_system_time(SystemTime::now() - Duration::from_secs(100 * 24 * 60 * 60));
+        for path in [
+            "logs/2021/app.log",
+            "logs/2021/.app.log",
+            "logs/2021/app.log.keep",
+            "logs/app.txt",
+            "old-project/node_modules/left-pad/index.js",
+            "old-project/node_modules/left-pad/node_modules",
+            "old-project/node_modules/left-pad",
+            "old-project/node_modules",
+        ] {
+            filetime::set_file_mtime(root.join(path), old).unwrap();
+        }
+
+        let rules: RuleSet = toml::from_str(&format!(
+            r#"
+            [[rule]]
+            name = "old logs"
+            pattern = "{root}/logs/**/*.log"
+            older_than_days = 30
+            mode = "force"
+
+            [[rule]]
+            name = "text at the top"
+            pattern = "{root}/*.txt"
+
+            [[rule]]
+            pattern = "{root}/**/*.keep"
+
+            [[rule]]
+            pattern = "**/node_modules"
+            target = "dir"
+            older_than_days = 90
+            "#,
+            root = root.display()
+        ))
+        .unwrap();
+
+        // Hidden and ignored files are left alone, like the scan left them out
+        let settings = ScanSettings::builder().dir(&root).hidden(false).ignore("*.keep").build();
+        let scan = scan(settings.clone(), |_| {}).unwrap();
+        let plan = rules.plan(&scan.id, &rules.evaluate(&scan, &settings, &ProtectedPaths::default()).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+
+        let mut picked: Vec<_> = plan.items.iter().map(|item| (item.path.strip_prefix(&root).unwrap(), item.mode)).collect();
+        picked.sort_by_key(|(path, _)| *path);
+        assert_eq!(
+            picked,
+            [
+                ("logs/2021/app.log".as_ref(), DeleteMode::Force),
+                ("old-project/node_modules".as_ref(), DeleteMode::Trash),
+            ]
+        );
+        assert_eq!(plan.total_bytes(), 30);
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    analyzer::{FileTreeNode, UsageMode},
+    errors::{BrokenSymlink, ScanError},
+    junk::Junk,
+    progress::{Progress, ProgressEvent},
+};
+
+type LargeFile = (PathBuf, u64);
+
+/// The results of a scan, filled in as it progresses
+#[derive(Clone)]
+pub struct Scan {
+    /// Identifies the scan in the audit log
+    pub id: String,
+    pub dirs: Vec<PathBuf>,
+    /// One directory tree per scanned root
+    pub tree: Vec<FileTreeNode>,
+    pub mode: UsageMode,
+    pub started_at: DateTime<Local>,
+    pub completed_at: Option<DateTime<Local>>,
+    pub current_file: Option<PathBuf>,
+    pub total_bytes: u64,
+    pub expected_bytes: Option<u64>,
+    pub largest_files: Vec<LargeFile>,
+    /// Directories with the most usage, largest first
+    pub largest_dirs: Vec<LargeFile>,
+    pub num_files: u64,
+    pub num_dirs: u64,
+    pub total_music: u64,
+    pub total_images: u64,
+    pub total_videos: u64,
+    pub total_documents: u64,
+    pub total_binaries: u64,
+    pub total_archives: u64,
+    pub total_other: u64,
+    pub dev_total_usage: u64,
+    pub developer_dirs: Vec<LargeFile>,
+    pub errors: Vec<ScanError>,
+    /// Symlinks that are dangling or loop, reported separately from errors
+    pub broken_symlinks: Vec<BrokenSymlink>,
+    pub junk: Vec<Junk>,
+    pub exclusions: Vec<(String, u64)>
+}
+
+fn scan_id(started_at: DateTime<Local>) -> String {
+    format!("{}-{}", started_at.format("%Y%m%d%H%M%S"), std::process::id())
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Scan::new()
+    }
+}
+
+impl Scan {
+    pub fn new() -> Scan {
+        let started_at = Local::now();
+
+        Scan {
+            id: scan_id(started_at),
+            dirs: vec![],
+            tree: vec![],
+            mode: UsageMode::Bytes,
+            started_at,
+            completed_at: None,
+            current_file: None,
+            num_files: 0,
+            num_dirs: 0,
+            total_bytes: 0,
+            expected_bytes: None,
+            largest_files: vec![],
+            largest_dirs: vec![],
+            total_music: 0,
+            total_images: 0,
+            total_videos: 0,
+            total_documents: 0,
+            total_binaries: 0,
+            total_archives: 0,
+            total_other: 0,
+            dev_total_usage: 0,
+            developer_dirs: vec![],
+            errors: vec![],
+            broken_symlinks: vec![],
+            junk: vec![],
+            exclusions: vec![],
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let elapsed = Local::now().signed_duration_since(self.started_at);
+
+        Progress {
+            bytes: self.total_bytes,
+            files: self.num_files,
+            expected_bytes: self.expected_bytes,
+            elapsed: elapsed.to_std().unwrap_or_default(),
+        }
+    }
+
+    /// Applies a progress event from the analyzer
+    pub fn apply(&mut self, event: ProgressEvent) {
+        // Results are final once the analyzer has written them
+        if self.completed_at.is_some() {
+            return;
+        }
+
+        match event {
+            ProgressEvent::Started { dirs, expected_bytes } => {
+                self.dirs = dirs;
+                self.expected_bytes = expected_bytes;
+            }
+            ProgressEvent::Update { current_file, bytes, files } => {
+                self.current_file = Some(current_file);
+                self.total_bytes = bytes;
+                self.num_files = files;
+            }
+            ProgressEvent::Finished => {
+                self.current_file = None;
+            }
+        }
+    }
+
+    /// Usage of each scanned root, in units of the scan's `UsageMode`
+    pub fn root_usage(&self) -> Vec<LargeFile> {
+        self.tree
+            .iter()
+            .map(|node| (node.path.clone(), node.len))
+            .collect()
+    }
+
+    /// The largest directories. With `leaf_most`, directories that contain another
+    /// listed directory are left out, so a heavy directory isn't shown again as
+    /// each of its ancestors.
+    pub fn large_dirs(&self, leaf_most: bool) -> Vec<LargeFile> {
+        if !leaf_most {
+            return self.largest_dirs.clone();
+        }
+
+        self.largest_dirs
+            .iter()
+            .filter(|(dir, _)| {
+                !self.largest_dirs
+                    .iter()
+                    .any(|(other, _)| other != dir && other.starts_with(dir))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Takes `path` and everything under it out of the results once it's been
+    /// deleted. `len` is its usage in the scan's mode. Totals by file type
+    /// aren't tracked per path, so they're left as they were.
+    pub fn remove_path(&mut self, path: &Path, len: u64) {
+        // Anything that isn't a directory in the tree is a single file
+        let (files, dirs) = match find_node(&self.tree, path) {
+            Some(node) => (node.files, count_dirs(node)),
+            None => (1, 0),
+        };
+
+        if self.mode == UsageMode::Bytes {
+            self.total_bytes = self.total_bytes.saturating_sub(len);
+        }
+        self.num_files = self.num_files.saturating_sub(files);
+        self.num_dirs = self.num_dirs.saturating_sub(dirs);
+
+        self.tree.retain(|node| node.path != path);
+        for node in self.tree.iter_mut() {
+            remove_from_tree(node, path, len, files);
+        }
+
+        remove_from_list(&mut self.largest_files, path, len);
+        remove_from_list(&mut self.largest_dirs, path, len);
+        remove_from_list(&mut self.developer_dirs, path, len);
+        self.dev_total_usage = self.developer_dirs.iter().map(|dir| dir.1).sum();
+
+        self.junk.retain(|junk| !junk.path.starts_with(path));
+        self.broken_symlinks.retain(|symlink| !symlink.path.starts_with(path));
+    }
+
+    /// The scanned roots, comma separated
+    pub fn dirs_label(&self) -> String {
+        self.dirs
+            .iter()
+            .map(|d| d.display().to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    pub fn clear(&mut self) {
+        self.dirs = vec![];
+        self.tree = vec![];
+        self.started_at = Local::now();
+        self.id = scan_id(self.started_at);
+        self.completed_at = None;
+        self.current_file = None;
+        self.total_bytes = 0;
+        self.expected_bytes = None;
+        self.num_files = 0;
+        self.num_dirs = 0;
+        self.largest_files = vec![];
+        self.largest_dirs = vec![];
+        self.total_music = 0;
+        self.total_images = 0;
+        self.total_videos = 0;
+        self.total_documents = 0;
+        self.total_archives= 0;
+        self.total_other = 0;
+        self.dev_total_usage = 0;
+        self.developer_dirs = vec![];
+        self.errors = vec![];
+        self.broken_symlinks = vec![];
+        self.junk = vec![];
+        self.exclusions = vec![];
+    }
+}
+
+/// The directory at `path` in `nodes`, at any depth
+fn find_node<'a>(nodes: &'a [FileTreeNode], path: &Path) -> Option<&'a FileTreeNode> {
+    let node = nodes.iter().find(|node| path.starts_with(&node.path))?;
+
+    match node.path == path {
+        true => Some(node),
+        false => find_node(&node.children, path),
+    }
+}
+
+/// Drops `path` from under `node`, shrinking the directories it was in by
+/// `len` and `files`
+fn remove_from_tree(node: &mut FileTreeNode, path: &Path, len: u64, files: u64) {
+    if node.path == path || !path.starts_with(&node.path) {
+        return;
+    }
+
+    node.len = node.len.saturating_sub(len);
+    node.files = node.files.saturating_sub(files);
+
+    node.children.retain(|child| child.path != path);
+    for child in node.children.iter_mut() {
+        remove_from_tree(child, path, len, files);
+    }
+}
+
+fn count_dirs(node: &FileTreeNode) -> u64 {
+    1 + node.children.iter().map(count_dirs).sum::<u64>()
+}
+
+/// Drops `path` and what's under it from a list sorted largest first, shrinking
+/// its ancestors in the list
+fn remove_from_list(list: &mut Vec<LargeFile>, path: &Path, len: u64) {
+    list.retain(|(entry, _)| !entry.starts_with(path));
+    for (entry, usage) in list.iter_mut() {
+        if path.starts_with(entry) {
+            *usage = usage.saturating_sub(len);
+        }
+    }
+    list.sort_by_key(|entry| Reverse(entry.1));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::Scan;
+    use crate::analyzer::FileTreeNode;
+
+    #[test]
+    fn leaf_most_dirs_skip_ancestors() {
+        let mut scan = Scan::new();
+        scan.largest_dirs = vec![
+            (PathBuf::from("/home/max"), 300),
+            (PathBuf::from("/home/max/videos"), 200),
+            (PathBuf::from("/home/max/videos/2019"), 150),
+            (PathBuf::from("/home/max/music"), 90),
+        ];
+
+        assert_eq!(scan.large_dirs(true), vec![
+            (PathBuf::from("/home/max/videos/2019"), 150),
+            (PathBuf::from("/home/max/music"), 90),
+        ]);
+        assert_eq!(scan.large_dirs(false).len(), 4);
+    }
+
+    #[test]
+    fn removed_path_leaves_results() {
+        let node = |path: &str, len, files, children| FileTreeNode {
+            path: PathBuf::from(path),
+            is_file: false,
+            len,
+            files,
+            children,
+            empty: false,
+        };
+
+        let mut scan = Scan::new();
+        scan.total_bytes = 300;
+        scan.num_files = 7;
+        scan.num_dirs = 4;
+        scan.tree = vec![node("/home/max", 300, 7, vec![
+            node("/home/max/videos", 200, 4, vec![node("/home/max/videos/2019", 150, 3, vec![])]),
+            node("/home/max/music", 90, 2, vec![]),
+        ])];
+        scan.largest_dirs = vec![
+            (PathBuf::from("/home/max"), 300),
+            (PathBuf::from("/home/max/videos"), 200),
+            (PathBuf::from("/home/max/videos/2019"), 150),
+            (PathBuf::from("/home/max/music"), 90),
+        ];
+
+        scan.remove_path(Path::new("/home/max/videos"), 200);
+
+        assert_eq!(scan.total_bytes, 100);
+        assert_eq!(scan.num_files, 3);
+        assert_eq!(scan.num_dirs, 2);
+        assert_eq!(scan.tree[0].len, 100);
+        assert_eq!(scan.tree[0].files, 3);
+        assert_eq!(scan.tree[0].children.len(), 1);
+        assert_eq!(scan.largest_dirs, vec![
+            (PathBuf::from("/home/max"), 100),
+            (PathBuf::from("/home/max/music"), 90),
+        ]);
+
+        // A file isn't in the tree, it only shrinks the directories holding it
+        scan.remove_path(Path::new("/home/max/music/song.mp3"), 40);
+
+        assert_eq!(scan.num_files, 2);
+        assert_eq!(scan.num_dirs, 2);
+        assert_eq!(scan.tree[0].files, 2);
+        assert_eq!(scan.tree[0].children[0].len, 50);
+    }
+}
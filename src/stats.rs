@@ -1,17 +1,89 @@
-use std::{borrow::Borrow, path::PathBuf};
+use std::{cmp::Reverse, path::{Path, PathBuf}};
 
-use colored::*;
 use glob::Pattern;
+use priority_queue::PriorityQueue;
 
-use crate::utils::bytes_to_human;
+use crate::{
+    analyzer::UsageMode,
+    errors::{BrokenSymlink, ScanError},
+    junk::{is_temp_file, Junk, JunkKind},
+};
 
-type LargeFile = (String, u64);
+type LargeFile = (PathBuf, u64);
+
+/// Keeps the `limit` largest entries pushed to it. The smallest kept entry sits at
+/// the top of a min-heap, so each push is O(log limit).
+pub struct TopN {
+    limit: usize,
+    queue: PriorityQueue<PathBuf, Reverse<u64>>,
+}
+
+impl TopN {
+    pub fn new(limit: u64) -> TopN {
+        TopN {
+            limit: limit as usize,
+            queue: PriorityQueue::with_capacity(limit.min(1024) as usize + 1),
+        }
+    }
+
+    pub fn push(&mut self, path: &Path, len: u64) {
+        if self.limit == 0 {
+            return;
+        }
+
+        if self.queue.len() >= self.limit {
+            match self.queue.peek() {
+                Some((_, Reverse(smallest))) if len > *smallest => {
+                    self.queue.pop();
+                }
+                _ => return,
+            }
+        }
+
+        self.queue.push(path.to_path_buf(), Reverse(len));
+    }
+
+    pub fn merge(&mut self, other: TopN) {
+        for (path, Reverse(len)) in other.queue {
+            self.push(&path, len);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// The kept entries, largest first
+    pub fn to_vec(&self) -> Vec<LargeFile> {
+        let mut entries: Vec<LargeFile> = self
+            .queue
+            .iter()
+            .map(|(path, Reverse(len))| (path.clone(), *len))
+            .collect();
+
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        entries
+    }
+}
 
 pub struct AnalyzerStats {
-    developer_dir_pattern: Pattern,
-    pub largest_files: Box<Vec<LargeFile>>,
+    developer_dir_patterns: Vec<Pattern>,
+    mode: UsageMode,
+    large_bytes: u64,
+    pub largest_files: TopN,
+    /// Directories with the most usage in the current mode, at any depth
+    pub largest_dirs: TopN,
     pub developer_dirs: Vec<LargeFile>,
+    pub errors: Vec<ScanError>,
+    pub broken_symlinks: Vec<BrokenSymlink>,
+    pub junk: Vec<Junk>,
     pub num_files: u64,
+    pub num_dirs: u64,
     pub total_music: u64,
     pub total_images: u64,
     pub total_videos: u64,
@@ -23,13 +95,21 @@ pub struct AnalyzerStats {
 }
 
 impl AnalyzerStats {
-    pub fn new() -> AnalyzerStats {
+    /// Stats keeping the `nlargest` largest files and directories. Files smaller than
+    /// `large_bytes` are never listed.
+    pub fn new(mode: UsageMode, developer_dir_patterns: Vec<Pattern>, nlargest: u64, large_bytes: u64) -> AnalyzerStats {
         AnalyzerStats {
-            //largest_files: PriorityQueue::with_capacity(100),
-            developer_dir_pattern: Pattern::new("**/node_modules").unwrap(),
-            largest_files: Box::new(vec![]),
+            developer_dir_patterns,
+            mode,
+            large_bytes,
+            largest_files: TopN::new(nlargest),
+            largest_dirs: TopN::new(nlargest),
             developer_dirs: vec![],
+            errors: vec![],
+            broken_symlinks: vec![],
+            junk: vec![],
             num_files: 0,
+            num_dirs: 0,
             total_music: 0,
             total_images: 0,
             total_videos: 0,
@@ -41,30 +121,92 @@ impl AnalyzerStats {
         }
     }
 
-    pub fn get_largest(&self) -> &Vec<LargeFile> {
-        return self.largest_files.borrow();
+    /// Empty stats with the same settings, for a worker walking part of the tree
+    pub fn fork(&self) -> AnalyzerStats {
+        AnalyzerStats::new(
+            self.mode,
+            self.developer_dir_patterns.clone(),
+            self.largest_files.limit as u64,
+            self.large_bytes,
+        )
     }
 
-    pub fn register_dir_usage(&mut self, path: &PathBuf, len: u64) {
-        let dirname = path.file_name().expect("Unable to process dir").to_str().unwrap();
+    /// Adds the stats collected by a worker
+    pub fn merge(&mut self, other: AnalyzerStats) {
+        self.largest_files.merge(other.largest_files);
+        self.largest_dirs.merge(other.largest_dirs);
 
-        if self.developer_dir_pattern.matches(dirname) {
+        self.developer_dirs.extend(other.developer_dirs);
+        self.errors.extend(other.errors);
+        self.broken_symlinks.extend(other.broken_symlinks);
+        self.junk.extend(other.junk);
+        self.num_files += other.num_files;
+        self.num_dirs += other.num_dirs;
+        self.total_music += other.total_music;
+        self.total_images += other.total_images;
+        self.total_videos += other.total_videos;
+        self.total_documents += other.total_documents;
+        self.total_binaries += other.total_binaries;
+        self.total_archives += other.total_archives;
+        self.total_other += other.total_other;
+        self.dev_total_usage += other.dev_total_usage;
+    }
+
+    pub fn get_largest(&self) -> Vec<LargeFile> {
+        self.largest_files.to_vec()
+    }
+
+    pub fn register_error(&mut self, error: ScanError) {
+        self.errors.push(error);
+    }
+
+    pub fn register_broken_symlink(&mut self, symlink: BrokenSymlink) {
+        self.broken_symlinks.push(symlink);
+    }
+
+    pub fn register_junk(&mut self, path: &Path, kind: JunkKind, len: u64) {
+        self.junk.push(Junk { path: path.to_path_buf(), kind, len });
+    }
+
+    /// Record a fully walked directory. `len` is its usage in the current mode.
+    pub fn register_dir_usage(&mut self, path: &Path, len: u64) {
+        self.num_dirs += 1;
+
+        let path_str = path.to_string_lossy();
+
+        if self.developer_dir_patterns.iter().any(|p| p.matches(&path_str)) {
             self.dev_total_usage += len;
-            self.developer_dirs.push((String::from(path.to_str().unwrap()), len));
+            self.developer_dirs.push((path.to_path_buf(), len));
+        }
+
+        self.largest_dirs.push(path, len);
+
+        // When counting files, the interesting entries are the directories holding them
+        if self.mode == UsageMode::Files {
+            self.largest_files.push(path, len);
         }
     }
 
-    pub fn register_file(&mut self, path_str: &str, len: u64, nlargest: u64, large_bytes: u64) {
-        // println!("{}", path_str);
+    pub fn register_file(&mut self, path: &Path, len: u64) {
+        // println!("{}", path.display());
         self.num_files += 1;
 
+        if is_temp_file(path) {
+            self.register_junk(path, JunkKind::TempFile, len);
+        } else if len == 0 {
+            self.register_junk(path, JunkKind::EmptyFile, 0);
+        }
+
         let mut mime_str = String::from("");
-        if let Some(mime) = mime_guess::from_path(path_str).first() {
+        if let Some(mime) = mime_guess::from_path(path).first() {
             mime_str = mime.to_string();
         };
 
-        self.push_largest(path_str, len, nlargest, large_bytes);
+        if self.mode == UsageMode::Bytes && len >= self.large_bytes {
+            self.largest_files.push(path, len);
+        }
 
+        let len = self.mode.weight(len);
 
         if mime_str.contains("image/") {
             self.total_images += len;
@@ -112,30 +254,29 @@ impl AnalyzerStats {
             _ => false
         }
     }
+}
 
-    pub fn push_largest(&mut self, path_str: &str, len: u64, nlargest: u64, large_bytes: u64) {
-        if len < large_bytes as u64 {
-            return;
-        }
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
 
-        if self.largest_files.len() == 0 {
-            self.largest_files.push((path_str.to_string(), len));
-            self.largest_files.sort_by(|a, b| b.1.cmp(&a.1));
-        } else { //else if self.largest_files.iter().any(|x| len > x.1) {
-            self.largest_files.push((path_str.to_string(), len));
-            self.largest_files.sort_by(|a, b| b.1.cmp(&a.1));
-        }
+    use super::TopN;
 
-        if self.largest_files.len() > nlargest as usize {
-            self.largest_files.truncate(nlargest as usize);
+    #[test]
+    fn top_n_keeps_largest() {
+        let mut top = TopN::new(3);
+        for (name, len) in [("a", 5), ("b", 50), ("c", 1), ("d", 20), ("e", 30), ("f", 2)] {
+            top.push(Path::new(name), len);
         }
-    }
 
-    pub fn print_largest(&self) {
-        let largest: &Box<Vec<(String, u64)>> = self.largest_files.borrow();
+        let mut other = TopN::new(3);
+        other.push(Path::new("g"), 40);
+        top.merge(other);
 
-        for s in largest.iter() {
-            println!("{} ({})", s.0.bright_white(), bytes_to_human(s.1).bold());
-        }
+        assert_eq!(top.to_vec(), vec![
+            (PathBuf::from("b"), 50),
+            (PathBuf::from("g"), 40),
+            (PathBuf::from("e"), 30),
+        ]);
     }
 }
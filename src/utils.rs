@@ -1,12 +1,347 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use human_bytes::human_bytes;
+use serde::{Deserialize, Serialize};
+use sysinfo::{DiskExt, System, SystemExt};
 
-pub fn is_hidden(path: &PathBuf) -> bool {
-    let path_str = path.file_name().unwrap().to_str().unwrap();
-    path_str.starts_with(".")
+use crate::analyzer::UsageMode;
+
+pub fn is_hidden(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().starts_with("."),
+        None => false
+    }
+}
+
+/// How sizes are printed: powers of 1000 (kB, MB) or powers of 1024 (KiB, MiB)
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeUnits {
+    Si,
+    #[default]
+    Iec,
+}
+
+impl FromStr for SizeUnits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "si" => Ok(SizeUnits::Si),
+            "iec" => Ok(SizeUnits::Iec),
+            _ => Err(format!("Unknown size units \"{}\", expected si or iec", s)),
+        }
+    }
+}
+
+/// Formats a byte count with one decimal, in `units`
+pub fn bytes_to_human(len: u64, units: SizeUnits) -> String {
+    let (base, suffixes) = match units {
+        SizeUnits::Si => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB", "EB"]),
+        SizeUnits::Iec => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+    };
+
+    let mut value = len as f64;
+    let mut i = 0;
+    while value >= base && i < suffixes.len() - 1 {
+        value /= base;
+        i += 1;
+    }
+
+    let value = format!("{:.1}", value);
+    format!("{} {}", value.trim_end_matches(".0"), suffixes[i])
+}
+
+/// Parses a size like "500M", "2GiB", "1.5 TB" or "4096" into bytes. SI suffixes
+/// (kB, MB) are powers of 1000, IEC suffixes (KiB, MiB) and single letters (K, M)
+/// are powers of 1024. Case is ignored.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => return Err(format!("Invalid size \"{}\"", text)),
+    };
+
+    let unit = unit.trim().to_ascii_lowercase();
+    let (prefix, base) = match unit.as_str() {
+        "" | "b" => ("", 1000_f64),
+        _ if unit.ends_with("ib") => (&unit[..unit.len() - 2], 1024_f64),
+        _ if unit.ends_with('b') => (&unit[..unit.len() - 1], 1000_f64),
+        _ => (unit.as_str(), 1024_f64),
+    };
+
+    let exponent = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        _ => return Err(format!("Unknown unit \"{}\" in size \"{}\"", unit, text)),
+    };
+
+    let bytes = number * base.powi(exponent);
+    if bytes > u64::MAX as f64 {
+        return Err(format!("Size \"{}\" is too large", text));
+    }
+
+    Ok(bytes.round() as u64)
+}
+
+/// Formats a usage value according to what it measures, sizes in `units`
+pub fn format_usage(value: u64, mode: UsageMode, units: SizeUnits) -> String {
+    match mode {
+        UsageMode::Bytes => bytes_to_human(value, units),
+        UsageMode::Files => format!("{} entries", value),
+    }
+}
+
+/// The device a path lives on, used to tell filesystems apart
+#[cfg(unix)]
+pub fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(path).ok().map(|meta| meta.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// The (device, inode) pair identifying the file behind `meta`
+#[cfg(unix)]
+pub fn file_id(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((meta.dev(), meta.ino()))
 }
 
-pub fn bytes_to_human(len: u64) -> String {
-    return human_bytes(len as f64);
-}
\ No newline at end of file
+#[cfg(not(unix))]
+pub fn file_id(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Size and free space of a mounted filesystem
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiskSpace {
+    pub mount_point: PathBuf,
+    pub total: u64,
+    pub available: u64,
+}
+
+impl DiskSpace {
+    /// Free space as a percentage of the total
+    pub fn free_percent(&self) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => self.available as f64 / total as f64 * 100.0,
+        }
+    }
+}
+
+/// The filesystem `dir` lives on, if it can be told
+pub fn disk_space(dir: &Path) -> Option<DiskSpace> {
+    let mut sys = System::new();
+    sys.refresh_disks_list();
+
+    let dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+
+    // The deepest mount point containing the directory is the one it lives on
+    sys.disks()
+        .iter()
+        .filter(|disk| dir.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().components().count())
+        .map(|disk| DiskSpace {
+            mount_point: disk.mount_point().to_path_buf(),
+            total: disk.total_space(),
+            available: disk.available_space(),
+        })
+}
+
+/// Returns the (total, free) inode counts of the filesystem mounted at `mount_point`
+#[cfg(unix)]
+pub fn inode_usage(mount_point: &Path) -> Option<(u64, u64)> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(mount_point.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) };
+    if ret != 0 {
+        return None;
+    }
+
+    let stat = unsafe { stat.assume_init() };
+
+    // Some filesystems (e.g. btrfs) don't have a fixed inode table
+    if stat.f_files == 0 {
+        return None;
+    }
+
+    Some((stat.f_files, stat.f_ffree))
+}
+
+#[cfg(not(unix))]
+pub fn inode_usage(_mount_point: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Doubles backslashes and writes control characters as `\xNN`
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Renders a path as a single line that can be turned back into the exact same
+/// path with `unescape_path`, even when it isn't valid UTF-8. Backslashes are
+/// doubled, and control characters and bytes that aren't part of a valid UTF-8
+/// sequence are written as `\xNN`.
+#[cfg(unix)]
+pub fn escape_path(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut bytes = path.as_os_str().as_bytes();
+    let mut escaped = String::with_capacity(bytes.len());
+
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(valid) => {
+                escaped.push_str(&escape_text(valid));
+                break;
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                escaped.push_str(&escape_text(std::str::from_utf8(valid).unwrap()));
+
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                for b in &rest[..invalid_len] {
+                    escaped.push_str(&format!("\\x{:02x}", b));
+                }
+                bytes = &rest[invalid_len..];
+            }
+        }
+    }
+
+    escaped
+}
+
+#[cfg(not(unix))]
+pub fn escape_path(path: &Path) -> String {
+    escape_text(&path.to_string_lossy())
+}
+
+fn unescape_bytes(escaped: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut chars = escaped.char_indices();
+
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some((_, '\\')) => bytes.push(b'\\'),
+            Some((i, 'x')) => {
+                let hex = escaped.get(i + 1..i + 3)?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                chars.nth(1);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Reverses `escape_path`. Returns `None` if `escaped` isn't a valid escaped path.
+#[cfg(unix)]
+pub fn unescape_path(escaped: &str) -> Option<PathBuf> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    unescape_bytes(escaped).map(|bytes| PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+pub fn unescape_path(escaped: &str) -> Option<PathBuf> {
+    unescape_bytes(escaped).and_then(|bytes| String::from_utf8(bytes).ok()).map(PathBuf::from)
+}
+
+/// Writes a path through `escape_path` and reads it back through
+/// `unescape_path`, for `#[serde(with = "crate::utils::escaped_path")]`, so
+/// paths that aren't valid UTF-8 survive being saved
+pub mod escaped_path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use super::{escape_path, unescape_path};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&escape_path(path))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        let escaped = String::deserialize(deserializer)?;
+
+        unescape_path(&escaped).ok_or_else(|| D::Error::custom(format!("invalid escaped path \"{}\"", escaped)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{escape_path, parse_size, unescape_path};
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500M"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5 TB"), Ok(1_500_000_000_000));
+        assert_eq!(parse_size("50 kb"), Ok(50_000));
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("MB").is_err());
+    }
+
+    #[test]
+    fn escape_path_round_trip() {
+        let path = Path::new("/data/photos\\2019/café\tcopy.jpg");
+        let escaped = escape_path(path);
+
+        assert_eq!(escaped, "/data/photos\\\\2019/café\\x09copy.jpg");
+        assert_eq!(unescape_path(&escaped).unwrap(), path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escape_path_non_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        // "caf\xe9" is Latin-1, not UTF-8
+        let path = Path::new(OsStr::from_bytes(b"/archive/caf\xe9.txt"));
+        let escaped = escape_path(path);
+
+        assert_eq!(escaped, "/archive/caf\\xe9.txt");
+        assert_eq!(unescape_path(&escaped).unwrap(), path);
+    }
+}